@@ -0,0 +1,452 @@
+//! End-to-end benchmark of menu generation on a synthetic XDG tree.
+//!
+//! Generates thousands of desktop files and a multi-level inherited icon
+//! theme in a temporary directory, runs the `box-menu-rs` binary against it
+//! with `--timings` and reports the median duration of each phase. The
+//! binary runs with a cleared environment and an empty `PATH`, so neither
+//! the settings of the user nor helpers such as `gsettings` are used.
+//!
+//! Without `--bench`, as when `cargo test --all-targets` builds and runs
+//! this target, only a small smoke run checks that the scenarios work.
+//!
+//! ```sh
+//! cargo bench --bench menu -- --save-baseline target/bench-baseline.txt
+//! # ... change code ...
+//! cargo bench --bench menu -- --baseline target/bench-baseline.txt
+//! ```
+//!
+//! Options after `--`:
+//! - `--entries N`: number of desktop files (default 3000, 20 for the smoke
+//!   run)
+//! - `--iterations N`: measured runs per scenario (default 10, 1 for the
+//!   smoke run)
+//! - `--save-baseline FILE`: store the medians in FILE
+//! - `--baseline FILE`: compare with FILE and exit with an error if a phase
+//!   got more than `--threshold` percent (default 25) slower
+
+use std::{
+    collections::BTreeMap,
+    env, fs,
+    path::{Path, PathBuf},
+    process::{self, Command},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+const CATEGORIES: &[&str] = &[
+    "AudioVideo;Audio;Player;",
+    "AudioVideo;Video;",
+    "Development;IDE;",
+    "Education;Languages;",
+    "Game;ArcadeGame;",
+    "Graphics;2DGraphics;RasterGraphics;",
+    "Network;WebBrowser;",
+    "Office;WordProcessor;",
+    "Science;Math;",
+    "Settings;DesktopSettings;",
+    "System;TerminalEmulator;",
+    "Utility;TextEditor;",
+    "X-Vendor-Specific;",
+];
+
+const OUTPUT_CATEGORIES: &[&str] = &[
+    "Development",
+    "Education",
+    "Games",
+    "Graphics",
+    "Internet",
+    "Multimedia",
+    "Office",
+    "Science",
+    "Settings",
+    "System",
+    "Utility",
+];
+
+/// Themes from most to least specific; each inherits from the next one.
+const THEMES: &[&str] = &["BenchTheme", "BenchMid", "BenchBase", "hicolor"];
+
+/// Directories of every theme as `(name, size, scalable)`.
+const THEME_DIRS: &[(&str, u32, bool)] = &[
+    ("16x16/apps", 16, false),
+    ("22x22/apps", 22, false),
+    ("24x24/apps", 24, false),
+    ("32x32/apps", 32, false),
+    ("48x48/apps", 48, false),
+    ("scalable/apps", 48, true),
+];
+
+/// Distinct icon names; several entries share an icon, as in real installs.
+const ICONS: usize = 1500;
+
+/// Phases shorter than this are too noisy to flag as regressions.
+const NOISE_FLOOR_MS: f64 = 1.0;
+
+struct Options {
+    entries: Option<usize>,
+    iterations: Option<usize>,
+    /// Set by `cargo bench`; otherwise only a smoke run is done.
+    bench: bool,
+    save_baseline: Option<PathBuf>,
+    baseline: Option<PathBuf>,
+    threshold: f64,
+}
+
+fn parse_options() -> Options {
+    let mut options = Options {
+        entries: None,
+        iterations: None,
+        bench: false,
+        save_baseline: None,
+        baseline: None,
+        threshold: 25.0,
+    };
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        let mut value = || {
+            args.next().unwrap_or_else(|| {
+                eprintln!("error: {} requires a value", arg);
+                process::exit(2);
+            })
+        };
+        match arg.as_str() {
+            "--entries" => options.entries = Some(value().parse().expect("invalid --entries")),
+            "--iterations" => {
+                options.iterations = Some(value().parse().expect("invalid --iterations"))
+            }
+            "--save-baseline" => options.save_baseline = Some(value().into()),
+            "--baseline" => options.baseline = Some(value().into()),
+            "--threshold" => options.threshold = value().parse().expect("invalid --threshold"),
+            // Passed by `cargo bench`.
+            "--bench" => options.bench = true,
+            _ => {
+                eprintln!("error: unknown argument {}", arg);
+                process::exit(2);
+            }
+        }
+    }
+    options
+}
+
+/// The synthetic `HOME` with its XDG directories.
+struct Fixture {
+    root: PathBuf,
+}
+
+impl Fixture {
+    fn create(entries: usize) -> Self {
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .expect("time went backwards")
+            .as_nanos();
+        let fixture = Self {
+            root: env::temp_dir().join(format!("box-menu-rs-bench-{}", timestamp)),
+        };
+        fs::create_dir_all(fixture.bin_dir()).expect("failed to create bin dir");
+        fixture.write_icon_themes();
+        fixture.write_desktop_files(entries);
+        fixture.write_config();
+        fixture
+    }
+
+    fn data_home(&self) -> PathBuf {
+        self.root.join("home/.local/share")
+    }
+
+    fn data_dir(&self) -> PathBuf {
+        self.root.join("usr/share")
+    }
+
+    fn config_file(&self) -> PathBuf {
+        self.root.join("home/.config/box-menu-rs/config.yml")
+    }
+
+    /// An empty directory used as `PATH`.
+    fn bin_dir(&self) -> PathBuf {
+        self.root.join("bin")
+    }
+
+    fn write_icon_themes(&self) {
+        let icons = self.data_dir().join("icons");
+        for (level, theme) in THEMES.iter().enumerate() {
+            let theme_dir = icons.join(theme);
+            let mut index = format!("[Icon Theme]\nName={}\n", theme);
+            if let Some(parent) = THEMES.get(level + 1) {
+                index.push_str(&format!("Inherits={}\n", parent));
+            }
+            let names: Vec<&str> = THEME_DIRS.iter().map(|(name, _, _)| *name).collect();
+            index.push_str(&format!("Directories={}\n", names.join(",")));
+            for (name, size, scalable) in THEME_DIRS {
+                fs::create_dir_all(theme_dir.join(name)).expect("failed to create theme dir");
+                index.push_str(&format!("\n[{}]\nSize={}\n", name, size));
+                if *scalable {
+                    index.push_str("MinSize=8\nMaxSize=512\nType=Scalable\n");
+                } else {
+                    index.push_str("Type=Fixed\n");
+                }
+            }
+            fs::write(theme_dir.join("index.theme"), index).expect("failed to write index.theme");
+        }
+
+        // Spread the icons over all inheritance levels and sizes, so lookups
+        // have to walk the inheritance chain.
+        for icon in 0..ICONS {
+            let theme = THEMES[icon % THEMES.len()];
+            let (dir, _, scalable) = THEME_DIRS[icon % THEME_DIRS.len()];
+            let extension = if scalable { "svg" } else { "png" };
+            let path = icons
+                .join(theme)
+                .join(dir)
+                .join(format!("bench-app-{}.{}", icon, extension));
+            fs::write(path, b"icon").expect("failed to write icon");
+        }
+        for category in OUTPUT_CATEGORIES {
+            let path = icons
+                .join("BenchBase/scalable/apps")
+                .join(format!("applications-{}.svg", category.to_lowercase()));
+            fs::write(path, b"icon").expect("failed to write icon");
+        }
+    }
+
+    fn write_desktop_files(&self, entries: usize) {
+        let system = self.data_dir().join("applications");
+        let vendor = system.join("vendor");
+        let user = self.data_home().join("applications");
+        for dir in [&system, &vendor, &user] {
+            fs::create_dir_all(dir).expect("failed to create applications dir");
+        }
+
+        for i in 0..entries {
+            let dir = match i % 10 {
+                0 => &user,
+                1 | 2 => &vendor,
+                _ => &system,
+            };
+            let icon = match i {
+                i if i % 11 == 0 => format!("bench-missing-{}", i),
+                i if i % 29 == 0 => self
+                    .data_dir()
+                    .join("icons/hicolor/48x48/apps/bench-app-3.png")
+                    .display()
+                    .to_string(),
+                i => format!("bench-app-{}", i % ICONS),
+            };
+            let mut contents = format!(
+                "[Desktop Entry]\n\
+                 Type=Application\n\
+                 Name=Bench App {i}\n\
+                 Name[de]=Testprogramm {i}\n\
+                 GenericName=Synthetic application\n\
+                 Comment=Generated for benchmarking\n\
+                 Exec=bench-app-{i} %U\n\
+                 Icon={icon}\n\
+                 Categories={categories}\n\
+                 Keywords=bench;synthetic;\n",
+                i = i,
+                icon = icon,
+                categories = CATEGORIES[i % CATEGORIES.len()],
+            );
+            if i % 13 == 0 {
+                contents.push_str("NoDisplay=true\n");
+            }
+            if i % 17 == 0 {
+                contents.push_str("OnlyShowIn=KDE;\n");
+            }
+            if i % 19 == 0 {
+                contents.push_str("NotShowIn=LXQt;\n");
+            }
+            if i % 23 == 0 {
+                contents.push_str("Hidden=true\n");
+            }
+            fs::write(dir.join(format!("bench-app-{}.desktop", i)), contents)
+                .expect("failed to write desktop file");
+        }
+    }
+
+    fn write_config(&self) {
+        let mappings = [
+            ("AudioVideo", "Multimedia"),
+            ("Audio", "Multimedia"),
+            ("Video", "Multimedia"),
+            ("Development", "Development"),
+            ("Education", "Education"),
+            ("Game", "Games"),
+            ("Graphics", "Graphics"),
+            ("Network", "Internet"),
+            ("Office", "Office"),
+            ("Science", "Science"),
+            ("Settings", "Settings"),
+            ("System", "System"),
+            ("Utility", "Utility"),
+        ];
+        let mut config = String::from("category_map:\n");
+        for (category, output) in mappings {
+            config.push_str(&format!("  {}:\n    output: {}\n", category, output));
+        }
+        config.push_str("options:\n  icon_theme: BenchTheme\n");
+
+        let path = self.config_file();
+        fs::create_dir_all(path.parent().unwrap()).expect("failed to create config dir");
+        fs::write(path, config).expect("failed to write config");
+    }
+
+    /// Runs the binary once and returns the `--timings` phases in ms.
+    fn run(&self, args: &[&str]) -> BTreeMap<String, f64> {
+        let output = Command::new(env!("CARGO_BIN_EXE_box-menu-rs"))
+            .arg("--config-file")
+            .arg(self.config_file())
+            .arg("--timings")
+            .args(args)
+            .env_clear()
+            .env("HOME", self.root.join("home"))
+            .env("PATH", self.bin_dir())
+            .env("XDG_DATA_HOME", self.data_home())
+            .env("XDG_DATA_DIRS", self.data_dir())
+            .env("XDG_CONFIG_HOME", self.root.join("home/.config"))
+            .env("XDG_CONFIG_DIRS", self.root.join("etc/xdg"))
+            .env("XDG_CACHE_HOME", self.root.join("home/.cache"))
+            .env("XDG_CURRENT_DESKTOP", "LXQt")
+            .env("LANG", "de_DE.UTF-8")
+            .output()
+            .expect("failed to run box-menu-rs");
+        if !output.status.success() {
+            panic!(
+                "box-menu-rs failed: {}",
+                String::from_utf8_lossy(&output.stderr)
+            );
+        }
+        parse_timings(&String::from_utf8_lossy(&output.stderr))
+    }
+}
+
+impl Drop for Fixture {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.root);
+    }
+}
+
+fn parse_timings(stderr: &str) -> BTreeMap<String, f64> {
+    stderr
+        .lines()
+        .filter_map(|line| {
+            let (phase, value) = line.split_once(':')?;
+            let value = value.trim().strip_suffix("ms")?.trim().parse().ok()?;
+            Some((phase.trim().to_string(), value))
+        })
+        .collect()
+}
+
+fn median(values: &mut [f64]) -> f64 {
+    values.sort_by(f64::total_cmp);
+    values[values.len() / 2]
+}
+
+/// Runs a scenario `iterations` times and returns the median of each phase
+/// keyed as `scenario/phase`.
+fn measure(
+    fixture: &Fixture,
+    scenario: &str,
+    args: &[&str],
+    iterations: usize,
+    results: &mut BTreeMap<String, f64>,
+) {
+    let mut samples: BTreeMap<String, Vec<f64>> = BTreeMap::new();
+    for _ in 0..iterations {
+        for (phase, value) in fixture.run(args) {
+            samples.entry(phase).or_default().push(value);
+        }
+    }
+    for (phase, mut values) in samples {
+        results.insert(format!("{}/{}", scenario, phase), median(&mut values));
+    }
+}
+
+fn read_baseline(path: &Path) -> BTreeMap<String, f64> {
+    let contents = fs::read_to_string(path).unwrap_or_else(|err| {
+        eprintln!("error: cannot read baseline {}: {}", path.display(), err);
+        process::exit(2);
+    });
+    contents
+        .lines()
+        .filter_map(|line| {
+            let (name, value) = line.split_once(' ')?;
+            Some((name.to_string(), value.parse().ok()?))
+        })
+        .collect()
+}
+
+fn main() {
+    let options = parse_options();
+    let (default_entries, default_iterations) = if options.bench { (3000, 10) } else { (20, 1) };
+    let entries = options.entries.unwrap_or(default_entries);
+    let iterations = options.iterations.unwrap_or(default_iterations);
+    let fixture = Fixture::create(entries);
+    println!(
+        "synthetic tree: {} desktop files, {} icons in {} inherited themes",
+        entries,
+        ICONS,
+        THEMES.len()
+    );
+
+    // Warm up the page cache so the first scenario is not penalized.
+    fixture.run(&["--no-cache"]);
+
+    let mut results = BTreeMap::new();
+    // Everything from scratch: parse all files and walk the icon themes.
+    measure(&fixture, "cold", &["--no-cache"], iterations, &mut results);
+    // Regenerated menu with icon lookups answered by the icon cache.
+    fixture.run(&["--rebuild-cache"]);
+    measure(
+        &fixture,
+        "icon-cache",
+        &["--rebuild-cache"],
+        iterations,
+        &mut results,
+    );
+    // Unchanged sources: the cached menu is printed.
+    measure(&fixture, "menu-cache", &[], iterations, &mut results);
+    // Only the top level of a lazy pipemenu.
+    measure(
+        &fixture,
+        "lazy",
+        &["--no-cache", "--lazy"],
+        iterations,
+        &mut results,
+    );
+
+    let baseline = options.baseline.as_deref().map(read_baseline);
+    let mut regressions = Vec::new();
+    for (name, value) in &results {
+        match baseline.as_ref().and_then(|baseline| baseline.get(name)) {
+            Some(&before) => {
+                let change = (value - before) / before.max(f64::EPSILON) * 100.0;
+                println!(
+                    "{:<24} {:>10.3} ms  (baseline {:>10.3} ms, {:+.1}%)",
+                    name, value, before, change
+                );
+                if change > options.threshold && value - before > NOISE_FLOOR_MS {
+                    regressions.push(name.clone());
+                }
+            }
+            None => println!("{:<24} {:>10.3} ms", name, value),
+        }
+    }
+
+    if let Some(path) = &options.save_baseline {
+        let contents: String = results
+            .iter()
+            .map(|(name, value)| format!("{} {}\n", name, value))
+            .collect();
+        fs::write(path, contents).expect("failed to write baseline");
+        println!("saved baseline to {}", path.display());
+    }
+
+    if !regressions.is_empty() {
+        eprintln!(
+            "error: slower than the baseline by more than {}%: {}",
+            options.threshold,
+            regressions.join(", ")
+        );
+        drop(fixture);
+        process::exit(1);
+    }
+}
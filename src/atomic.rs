@@ -0,0 +1,48 @@
+use std::{fs, io, path::Path, process};
+
+/// Replaces `path` with `contents` so that readers never see a partially
+/// written file: the data goes to a sibling temporary file first, which is
+/// then renamed over the target.
+pub fn write_file(path: &Path, contents: &[u8]) -> io::Result<()> {
+    let file_name = path
+        .file_name()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "path has no file name"))?;
+    let mut tmp_name = file_name.to_os_string();
+    tmp_name.push(format!(".tmp-{}", process::id()));
+    let tmp_path = path.with_file_name(tmp_name);
+
+    if let Some(parent) = path
+        .parent()
+        .filter(|parent| !parent.as_os_str().is_empty())
+    {
+        fs::create_dir_all(parent)?;
+    }
+    fs::write(&tmp_path, contents)?;
+    fs::rename(&tmp_path, path).inspect_err(|_| {
+        let _ = fs::remove_file(&tmp_path);
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::{SystemTime, UNIX_EPOCH};
+
+    #[test]
+    fn write_file_replaces_existing_content() {
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .expect("time went backwards")
+            .as_nanos();
+        let dir = std::env::temp_dir().join(format!("box-menu-rs-test-atomic-{}", timestamp));
+        let path = dir.join("menu.xml");
+
+        write_file(&path, b"first").expect("failed to write file");
+        write_file(&path, b"second").expect("failed to replace file");
+
+        assert_eq!(fs::read_to_string(&path).unwrap(), "second");
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 1);
+
+        fs::remove_dir_all(&dir).expect("failed to remove temp dir");
+    }
+}
@@ -0,0 +1,267 @@
+use crate::config::Config;
+use crate::icon::{IconCaching, IconResolver, configured_icons};
+use crate::menu::{Entry, MenuNode, Scope, make_entry, make_scoped_entries};
+use crate::timings::Timings;
+use crate::visibility::visibility_exclusion_reason;
+use crate::{parallel, xdg_menu};
+use freedesktop_desktop_entry::DesktopEntry;
+use std::{collections::HashSet, error::Error};
+
+/// A generated menu tree.
+pub struct Menu {
+    pub root: MenuNode,
+    /// Label and reason of every entry hidden by the visibility filter.
+    pub excluded: Vec<(String, String)>,
+}
+
+/// Turns desktop entries into a [`Menu`] according to a [`Config`], either
+/// via `category_map` or the XDG menu file set in `options.xdg_menu`.
+///
+/// ```no_run
+/// use box_menu::builder::MenuBuilder;
+/// use box_menu::config::Config;
+/// use box_menu::source::{EntrySource, SystemEntries};
+/// use box_menu::timings::Timings;
+///
+/// let config = Config::default();
+/// let locales = vec!["de".to_string()];
+/// let entries = SystemEntries.entries(&locales, &mut Timings::new(false));
+/// let menu = MenuBuilder::new(&config)
+///     .locales(&locales)
+///     .build(&entries)
+///     .unwrap();
+/// for (path, node) in menu.root.children("") {
+///     println!("{}: {} entries", path, node.entries().count());
+/// }
+/// ```
+pub struct MenuBuilder<'a> {
+    pub(crate) config: &'a Config,
+    pub(crate) locales: &'a [String],
+    icons: Option<&'a dyn IconResolver>,
+    pub(crate) current_desktop: Option<&'a HashSet<String>>,
+    pub(crate) scope: Scope,
+}
+
+impl<'a> MenuBuilder<'a> {
+    pub fn new(config: &'a Config) -> Self {
+        Self {
+            config,
+            locales: &[],
+            icons: None,
+            current_desktop: None,
+            scope: Scope::new(None, false),
+        }
+    }
+
+    /// Locales used for the entry labels, most preferred first.
+    pub fn locales(mut self, locales: &'a [String]) -> Self {
+        self.locales = locales;
+        self
+    }
+
+    /// Resolver for the entry icons; by default the one of
+    /// [`configured_icons`] for the configuration.
+    pub fn icons(mut self, icons: &'a dyn IconResolver) -> Self {
+        self.icons = Some(icons);
+        self
+    }
+
+    /// Parsed `XDG_CURRENT_DESKTOP` for the `OnlyShowIn`/`NotShowIn` checks.
+    pub fn current_desktop(mut self, current_desktop: Option<&'a HashSet<String>>) -> Self {
+        self.current_desktop = current_desktop;
+        self
+    }
+
+    /// Restricts icon lookups to the part of the tree that is printed.
+    pub fn scope(mut self, scope: Scope) -> Self {
+        self.scope = scope;
+        self
+    }
+
+    pub fn build(&self, entries: &[DesktopEntry]) -> Result<Menu, Box<dyn Error>> {
+        self.build_with_timings(entries, &mut Timings::new(false))
+    }
+
+    /// Like [`build`](Self::build), recording the phases in `timings`.
+    pub fn build_with_timings(
+        &self,
+        entries: &[DesktopEntry],
+        timings: &mut Timings,
+    ) -> Result<Menu, Box<dyn Error>> {
+        let default_icons;
+        let icons = match self.icons {
+            Some(icons) => icons,
+            None => {
+                default_icons = configured_icons(&self.config.options, IconCaching::Memory);
+                &*default_icons
+            }
+        };
+        match self.config.options.xdg_menu.as_deref() {
+            Some(menu_file) => xdg_menu::load_tree(menu_file, entries, self, icons, timings),
+            None => {
+                let mut excluded = Vec::new();
+                let root = self.category_tree(entries, icons, &mut excluded, timings);
+                Ok(Menu { root, excluded })
+            }
+        }
+    }
+
+    /// Entries listed in `options.favorites`, in configured order. Ids may be
+    /// given with or without the `.desktop` suffix.
+    pub fn favorites(&self, entries: &[DesktopEntry]) -> Vec<Entry> {
+        let default_icons;
+        let icons = match self.icons {
+            Some(icons) => icons,
+            None => {
+                default_icons = configured_icons(&self.config.options, IconCaching::Memory);
+                &*default_icons
+            }
+        };
+        self.config
+            .options
+            .favorites
+            .iter()
+            .filter_map(|id| {
+                let id = id.strip_suffix(".desktop").unwrap_or(id);
+                entries.iter().find(|entry| entry.id() == id)
+            })
+            .map(|entry| make_entry(entry, self.locales, self.config, icons))
+            .collect()
+    }
+
+    /// Builds the menu tree by mapping desktop entry categories via `category_map`.
+    fn category_tree(
+        &self,
+        all_entries: &[DesktopEntry],
+        icons: &dyn IconResolver,
+        excluded_entries: &mut Vec<(String, String)>,
+        timings: &mut Timings,
+    ) -> MenuNode {
+        let cfg = self.config;
+        let placements = timings.time("filter", || {
+            all_entries
+                .iter()
+                .filter(|x| x.categories().is_some())
+                .filter(|x| {
+                    if !cfg.options.visibility_filter {
+                        return true;
+                    }
+
+                    if let Some(reason) = visibility_exclusion_reason(x, self.current_desktop) {
+                        let label = x.full_name(self.locales).unwrap_or_default().to_string();
+                        excluded_entries.push((label, reason));
+                        false
+                    } else {
+                        true
+                    }
+                })
+                .filter_map(|entry| {
+                    let output_names = output_categories(entry, cfg);
+                    (!output_names.is_empty()).then_some((entry, output_names))
+                })
+                .collect::<Vec<_>>()
+        });
+
+        let menu_entries = timings.time("icons", || {
+            parallel::map(&placements, |(entry, output_names)| {
+                let menus: Vec<(&str, String)> = output_names
+                    .iter()
+                    .map(|name| (name.as_str(), cfg.icon_for_category(name)))
+                    .collect();
+                make_scoped_entries(entry, self.locales, cfg, icons, &self.scope, &menus)
+            })
+        });
+
+        let mut root = cfg.empty_tree();
+        for ((_, output_names), entries) in placements.iter().zip(menu_entries) {
+            for (output_name, menu_entry) in output_names.iter().zip(entries) {
+                root.insert(output_name, menu_entry);
+            }
+        }
+        root
+    }
+}
+
+/// Output categories `entry` is inserted into: the highest priority mapped
+/// category with `options.category_priority`, otherwise every mapped one.
+pub(crate) fn output_categories(entry: &DesktopEntry, cfg: &Config) -> Vec<String> {
+    let mapped_categories: Vec<String> = entry
+        .categories()
+        .unwrap_or_default()
+        .into_iter()
+        .filter(|k| !k.is_empty())
+        .filter(|k| cfg.category_map.contains_key(&k[..]))
+        .map(|k| k.to_string())
+        .collect();
+
+    if mapped_categories.is_empty() {
+        return Vec::new();
+    }
+
+    if cfg.options.category_priority {
+        let entries_category = mapped_categories
+            .into_iter()
+            .map(|c| {
+                let c_str: &str = c.as_ref();
+                let mapped_category = cfg.category_map.get(c_str).unwrap();
+                let output_name = mapped_category.output.as_deref().unwrap_or(c_str);
+                let priority = mapped_category.priority.unwrap_or(0);
+                (priority, output_name.to_string())
+            })
+            .max_by(|a, b| a.0.cmp(&b.0).then(a.1.cmp(&b.1)))
+            .map(|(_, output_name)| output_name)
+            .unwrap();
+        vec![entries_category]
+    } else {
+        // Insert into every matching mapped category by default.
+        mapped_categories
+            .into_iter()
+            .map(|c| {
+                let c_str: &str = c.as_ref();
+                let mapped_category = cfg.category_map.get(c_str).unwrap();
+                mapped_category
+                    .output
+                    .clone()
+                    .unwrap_or_else(|| c.to_string())
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::icon::NoIcons;
+    use crate::source::{EntrySource, MemoryEntries};
+
+    #[test]
+    fn build_places_entries_by_category_map() {
+        let entries = MemoryEntries::new()
+            .with(
+                "/fixtures/player.desktop",
+                "[Desktop Entry]\nType=Application\nName=Player\nExec=player\nIcon=player\nCategories=AudioVideo;Audio;\n",
+            )
+            .with(
+                "/fixtures/hidden.desktop",
+                "[Desktop Entry]\nType=Application\nName=Hidden\nExec=hidden\nNoDisplay=true\nCategories=Utility;\n",
+            )
+            .with(
+                "/fixtures/unmapped.desktop",
+                "[Desktop Entry]\nType=Application\nName=Unmapped\nExec=unmapped\nCategories=Unknown;\n",
+            )
+            .entries(&[], &mut Timings::new(false));
+        let config = Config::default();
+        let menu = MenuBuilder::new(&config)
+            .icons(&NoIcons)
+            .build(&entries)
+            .expect("failed to build menu");
+
+        let multimedia = menu.root.subtree("Multimedia").expect("missing category");
+        let labels: Vec<_> = multimedia.entries().map(|e| e.label.as_str()).collect();
+        assert_eq!(labels, ["Player"]);
+        assert!(multimedia.entries().all(|e| e.icon.is_none()));
+        assert_eq!(menu.root.subtree("Utility").unwrap().entries().count(), 0);
+        assert_eq!(menu.excluded.len(), 1);
+        assert_eq!(menu.excluded[0].0, "Hidden");
+    }
+}
@@ -0,0 +1,338 @@
+use crate::{atomic, xdg};
+use std::{
+    collections::{HashMap, hash_map::DefaultHasher},
+    fmt::Write as _,
+    fs,
+    hash::{Hash, Hasher},
+    io,
+    path::{Path, PathBuf},
+    time::UNIX_EPOCH,
+};
+
+const FINGERPRINT_PREFIX: &str = "box-menu-rs-cache ";
+
+/// `$XDG_CACHE_HOME/box-menu-rs`.
+pub fn cache_dir() -> PathBuf {
+    xdg::cache_home().join("box-menu-rs")
+}
+
+/// Collects everything a generated menu depends on, so that a cached menu
+/// is only reused while none of it changed.
+#[derive(Default)]
+pub struct Fingerprint {
+    text: String,
+}
+
+impl Fingerprint {
+    pub fn new() -> Self {
+        let mut fingerprint = Self::default();
+        fingerprint.value("version", env!("CARGO_PKG_VERSION"));
+        fingerprint
+    }
+
+    pub fn value(&mut self, name: &str, value: &str) {
+        let _ = writeln!(self.text, "{}={}", name, value);
+    }
+
+    /// Records the modification time and size of `path`, or its absence.
+    pub fn file(&mut self, path: &Path) {
+        let _ = writeln!(self.text, "{}:{}", path.display(), stat(path));
+    }
+
+    /// Records `dir` and all directories below it. Installing, removing or
+    /// replacing a desktop file changes the modification time of the
+    /// directory containing it.
+    pub fn tree(&mut self, dir: &Path) {
+        self.file(dir);
+        let Ok(read_dir) = fs::read_dir(dir) else {
+            return;
+        };
+        let mut subdirs: Vec<PathBuf> = read_dir
+            .filter_map(Result::ok)
+            .map(|entry| entry.path())
+            .filter(|path| path.is_dir())
+            .collect();
+        subdirs.sort();
+        for subdir in subdirs {
+            self.tree(&subdir);
+        }
+    }
+
+    fn digest(&self) -> String {
+        let mut hasher = DefaultHasher::new();
+        self.text.hash(&mut hasher);
+        format!("{:016x}", hasher.finish())
+    }
+}
+
+fn stat(path: &Path) -> String {
+    match fs::metadata(path) {
+        Ok(metadata) => {
+            let modified = metadata
+                .modified()
+                .ok()
+                .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+                .map(|time| time.as_nanos())
+                .unwrap_or_default();
+            format!("{}:{}", modified, metadata.len())
+        }
+        Err(_) => "missing".into(),
+    }
+}
+
+/// A rendered menu stored in the cache directory for one set of command line
+/// options.
+pub struct MenuCache {
+    path: PathBuf,
+    fingerprint: String,
+}
+
+impl MenuCache {
+    /// `key` identifies the options influencing the output; each key gets its
+    /// own cache file.
+    pub fn new(dir: &Path, key: &str, fingerprint: &Fingerprint) -> Self {
+        let mut hasher = DefaultHasher::new();
+        key.hash(&mut hasher);
+        Self {
+            path: dir.join(format!("menu-{:016x}", hasher.finish())),
+            fingerprint: fingerprint.digest(),
+        }
+    }
+
+    /// The cached menu, if it was generated with the same fingerprint.
+    pub fn load(&self) -> Option<String> {
+        let contents = fs::read_to_string(&self.path).ok()?;
+        let (header, menu) = contents.split_once('\n')?;
+        (header.strip_prefix(FINGERPRINT_PREFIX)? == self.fingerprint).then(|| menu.to_string())
+    }
+
+    pub fn store(&self, menu: &str) -> io::Result<()> {
+        let contents = format!("{}{}\n{}", FINGERPRINT_PREFIX, self.fingerprint, menu);
+        atomic::write_file(&self.path, contents.as_bytes())
+    }
+}
+
+/// Identifies one icon lookup.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct IconKey {
+    pub name: String,
+    pub theme: String,
+    pub size: u16,
+    pub scale: u16,
+    pub svg: bool,
+    /// Whether files that are no valid images were skipped.
+    pub validated: bool,
+}
+
+/// Results of icon lookups, including negative ones, kept across runs in
+/// the cache directory until the icon theme fingerprint changes.
+pub struct IconCache {
+    path: PathBuf,
+    fingerprint: String,
+    entries: HashMap<IconKey, Option<PathBuf>>,
+    dirty: bool,
+}
+
+impl IconCache {
+    /// Loads the cached lookups, or starts empty if they were recorded with
+    /// a different fingerprint.
+    pub fn load(dir: &Path, fingerprint: &Fingerprint) -> Self {
+        let mut cache = Self {
+            path: dir.join("icon-lookups"),
+            fingerprint: fingerprint.digest(),
+            entries: HashMap::new(),
+            dirty: false,
+        };
+        let Ok(contents) = fs::read_to_string(&cache.path) else {
+            return cache;
+        };
+        let mut lines = contents.lines();
+        if lines
+            .next()
+            .and_then(|header| header.strip_prefix(FINGERPRINT_PREFIX))
+            != Some(cache.fingerprint.as_str())
+        {
+            return cache;
+        }
+        for line in lines {
+            let fields: Vec<&str> = line.split('\t').collect();
+            let [theme, size, scale, format, checks, name, path] = fields[..] else {
+                continue;
+            };
+            let (Ok(size), Ok(scale)) = (size.parse(), scale.parse()) else {
+                continue;
+            };
+            let key = IconKey {
+                name: name.to_string(),
+                theme: theme.to_string(),
+                size,
+                scale,
+                svg: format == "svg",
+                validated: checks == "validated",
+            };
+            let path = (!path.is_empty()).then(|| PathBuf::from(path));
+            cache.entries.insert(key, path);
+        }
+        cache
+    }
+
+    /// The recorded result for `key`; `Some(None)` is a cached miss. Hits
+    /// whose file disappeared are treated as unknown.
+    pub fn get(&self, key: &IconKey) -> Option<Option<PathBuf>> {
+        match self.entries.get(key)? {
+            Some(path) if !path.exists() => None,
+            result => Some(result.clone()),
+        }
+    }
+
+    pub fn insert(&mut self, key: IconKey, path: Option<PathBuf>) {
+        if self.entries.get(&key) != Some(&path) {
+            self.entries.insert(key, path);
+            self.dirty = true;
+        }
+    }
+
+    /// Writes the cache back if lookups were added since loading it.
+    pub fn save(&mut self) -> io::Result<()> {
+        if !self.dirty {
+            return Ok(());
+        }
+        let is_storable = |value: &str| !value.contains(['\t', '\n']);
+        let mut lines: Vec<String> = self
+            .entries
+            .iter()
+            .map(|(key, path)| {
+                let path = path
+                    .as_deref()
+                    .map(|path| path.to_string_lossy().into_owned())
+                    .unwrap_or_default();
+                (key, path)
+            })
+            .filter(|(key, path)| {
+                is_storable(&key.name) && is_storable(&key.theme) && is_storable(path)
+            })
+            .map(|(key, path)| {
+                format!(
+                    "{}\t{}\t{}\t{}\t{}\t{}\t{}",
+                    key.theme,
+                    key.size,
+                    key.scale,
+                    if key.svg { "svg" } else { "raster" },
+                    if key.validated { "validated" } else { "any" },
+                    key.name,
+                    path
+                )
+            })
+            .collect();
+        lines.sort();
+
+        let mut contents = format!("{}{}\n", FINGERPRINT_PREFIX, self.fingerprint);
+        for line in lines {
+            contents.push_str(&line);
+            contents.push('\n');
+        }
+        atomic::write_file(&self.path, contents.as_bytes())?;
+        self.dirty = false;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::SystemTime;
+
+    fn temp_dir() -> PathBuf {
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .expect("time went backwards")
+            .as_nanos();
+        std::env::temp_dir().join(format!("box-menu-rs-test-cache-{}", timestamp))
+    }
+
+    #[test]
+    fn menu_cache_is_only_reused_with_same_fingerprint() {
+        let dir = temp_dir();
+        let mut fingerprint = Fingerprint::new();
+        fingerprint.value("locale", "de_DE");
+
+        let cache = MenuCache::new(&dir, "openbox", &fingerprint);
+        assert_eq!(cache.load(), None);
+        cache
+            .store("<openbox_menu />\n")
+            .expect("failed to store menu");
+        assert_eq!(cache.load().as_deref(), Some("<openbox_menu />\n"));
+
+        let other_key = MenuCache::new(&dir, "fluxbox", &fingerprint);
+        assert_eq!(other_key.load(), None);
+
+        fingerprint.value("locale", "en_US");
+        let changed = MenuCache::new(&dir, "openbox", &fingerprint);
+        assert_eq!(changed.load(), None);
+
+        fs::remove_dir_all(&dir).expect("failed to remove temp dir");
+    }
+
+    #[test]
+    fn fingerprint_tree_changes_when_desktop_file_is_added() {
+        let dir = temp_dir();
+        let nested = dir.join("applications/kde");
+        fs::create_dir_all(&nested).expect("failed to create temp dir");
+
+        let digest = |dir: &Path| {
+            let mut fingerprint = Fingerprint::new();
+            fingerprint.tree(dir);
+            fingerprint.digest()
+        };
+        let before = digest(&dir);
+        assert_eq!(digest(&dir), before);
+
+        fs::write(nested.join("app.desktop"), "[Desktop Entry]\n").expect("failed to write file");
+        assert_ne!(digest(&dir), before);
+
+        fs::remove_dir_all(&dir).expect("failed to remove temp dir");
+    }
+
+    #[test]
+    fn icon_cache_round_trips_hits_and_misses() {
+        let dir = temp_dir();
+        let icon = dir.join("firefox.png");
+        fs::create_dir_all(&dir).expect("failed to create temp dir");
+        fs::write(&icon, b"png").expect("failed to write icon");
+
+        let key = |name: &str| IconKey {
+            name: name.to_string(),
+            theme: "Adwaita".into(),
+            size: 24,
+            scale: 1,
+            svg: false,
+            validated: false,
+        };
+        let mut fingerprint = Fingerprint::new();
+        fingerprint.value("theme", "Adwaita");
+
+        let mut cache = IconCache::load(&dir, &fingerprint);
+        assert_eq!(cache.get(&key("firefox")), None);
+        cache.insert(key("firefox"), Some(icon.clone()));
+        cache.insert(key("missing"), None);
+        cache.save().expect("failed to save icon cache");
+
+        let cache = IconCache::load(&dir, &fingerprint);
+        assert_eq!(cache.get(&key("firefox")), Some(Some(icon.clone())));
+        assert_eq!(cache.get(&key("missing")), Some(None));
+        let validated = IconKey {
+            validated: true,
+            ..key("missing")
+        };
+        assert_eq!(cache.get(&validated), None);
+
+        fs::remove_file(&icon).expect("failed to remove icon");
+        assert_eq!(cache.get(&key("firefox")), None);
+
+        fingerprint.value("theme", "breeze");
+        let cache = IconCache::load(&dir, &fingerprint);
+        assert_eq!(cache.get(&key("missing")), None);
+
+        fs::remove_dir_all(&dir).expect("failed to remove temp dir");
+    }
+}
@@ -1,9 +1,20 @@
-use clap::{Parser, ValueEnum};
+use box_menu::{format::OutputFormat, list::ListAction};
+use clap::{Parser, Subcommand, ValueEnum};
 use std::path::PathBuf;
 
 #[derive(Debug, Parser)]
-#[command(author, version, about = "Generate an Openbox-compatible application menu", long_about = None)]
+#[command(
+    author,
+    version,
+    about = "Generate an Openbox-compatible application menu",
+    long_about = None,
+    args_conflicts_with_subcommands = true,
+    subcommand_precedence_over_arg = true
+)]
 pub struct CliOptions {
+    #[command(subcommand)]
+    command: Option<Command>,
+
     #[arg(
         value_name = "NAME",
         help = "Desktop entry Name substring to inspect when using --list program.",
@@ -13,6 +24,7 @@ pub struct CliOptions {
 
     #[arg(
         long = "config-file",
+        global = true,
         value_name = "PATH",
         help = "Load configuration from a specific YAML file instead of the default XDG config"
     )]
@@ -24,22 +36,121 @@ pub struct CliOptions {
         help = "List discovered desktop entries by action."
     )]
     list: Option<ListAction>,
+
+    #[arg(
+        long = "format",
+        global = true,
+        value_name = "FORMAT",
+        default_value = "openbox",
+        help = "Menu syntax to generate."
+    )]
+    format: OutputFormat,
+
+    #[arg(
+        long = "lazy",
+        help = "Emit submenus as pipemenu stubs that run box-menu-rs --submenu when opened."
+    )]
+    lazy: bool,
+
+    #[arg(
+        long = "submenu",
+        value_name = "PATH",
+        help = "Only generate the content of the slash-separated output category PATH."
+    )]
+    submenu: Option<String>,
+
+    #[arg(
+        long = "static",
+        global = true,
+        help = "Generate a complete Openbox/labwc menu.xml with a root-menu instead of a pipemenu."
+    )]
+    static_menu: bool,
+
+    #[arg(
+        long = "template",
+        global = true,
+        value_name = "PATH",
+        help = "menu.xml template whose {{apps}} and {{favorites}} placeholders are filled (implies --static)."
+    )]
+    template: Option<PathBuf>,
+
+    #[arg(
+        long = "output",
+        global = true,
+        value_name = "PATH",
+        help = "Atomically replace PATH with the generated menu instead of printing it."
+    )]
+    output: Option<PathBuf>,
+
+    #[arg(
+        long = "timings",
+        global = true,
+        help = "Print the duration of each generation phase to stderr."
+    )]
+    timings: bool,
+
+    #[arg(
+        long = "no-cache",
+        help = "Neither read nor update the cached menu in $XDG_CACHE_HOME/box-menu-rs."
+    )]
+    no_cache: bool,
+
+    #[arg(
+        long = "rebuild-cache",
+        conflicts_with = "no_cache",
+        help = "Regenerate the menu and replace the cached copy."
+    )]
+    rebuild_cache: bool,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    #[command(
+        about = "Export the category mapping as an XDG applications.menu with .directory files"
+    )]
+    ExportXdgMenu {
+        #[arg(
+            long = "output-dir",
+            value_name = "DIR",
+            help = "Directory to write the menu into [default: $XDG_CONFIG_HOME/menus]"
+        )]
+        output_dir: Option<PathBuf>,
+    },
+    #[command(
+        about = "Regenerate the --output menu whenever desktop files, the config or icon themes change"
+    )]
+    Watch {
+        #[arg(
+            long = "reconfigure",
+            value_name = "WM",
+            help = "Ask the window manager to reload its menu after each rewrite."
+        )]
+        reconfigure: Option<Reconfigure>,
+
+        #[arg(
+            long = "debounce",
+            value_name = "MS",
+            default_value_t = 1000,
+            help = "Wait until no change happened for MS milliseconds before regenerating."
+        )]
+        debounce: u64,
+    },
 }
 
 #[derive(Copy, Clone, Debug, ValueEnum)]
 #[value(rename_all = "kebab-case")]
-pub enum ListAction {
-    #[value(help = "All discovered desktop entries.")]
-    All,
-    #[value(help = "Entries with missing entry icon lookup.")]
-    MissingIcons,
-    #[value(help = "Hidden entries excluded by visibility filtering.")]
-    Excluded,
-    #[value(help = "Inspect a specific entry by Name.")]
-    Program,
+pub enum Reconfigure {
+    #[value(help = "Run labwc --reconfigure.")]
+    Labwc,
+    #[value(help = "Run openbox --reconfigure.")]
+    Openbox,
 }
 
 impl CliOptions {
+    pub fn command(&self) -> Option<&Command> {
+        self.command.as_ref()
+    }
+
     pub fn program_name(&self) -> Option<&str> {
         self.program_name.as_deref()
     }
@@ -51,4 +162,116 @@ impl CliOptions {
     pub fn list_action(&self) -> Option<ListAction> {
         self.list
     }
+
+    pub fn format(&self) -> OutputFormat {
+        self.format
+    }
+
+    pub fn lazy(&self) -> bool {
+        self.lazy
+    }
+
+    pub fn submenu(&self) -> Option<&str> {
+        self.submenu.as_deref()
+    }
+
+    /// Command line of the pipemenu stub generating the submenu at `path`,
+    /// carrying over the options that influence its content and `--no-cache`.
+    pub fn submenu_command(&self, path: &str) -> String {
+        let mut args = vec![program()];
+        if let Some(config_file) = &self.config_file {
+            // The window manager runs the stub from its own directory.
+            let config_file = std::path::absolute(config_file).unwrap_or(config_file.clone());
+            args.push("--config-file".into());
+            args.push(config_file.to_string_lossy().into_owned());
+        }
+        // `--rebuild-cache` is left out: every opened submenu would replace
+        // the cached one again.
+        if self.no_cache {
+            args.push("--no-cache".into());
+        }
+        args.push("--lazy".into());
+        args.push("--submenu".into());
+        args.push(path.to_string());
+        args.iter()
+            .map(|arg| shell_quote(arg))
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+
+    pub fn timings(&self) -> bool {
+        self.timings
+    }
+
+    pub fn no_cache(&self) -> bool {
+        self.no_cache
+    }
+
+    pub fn rebuild_cache(&self) -> bool {
+        self.rebuild_cache
+    }
+
+    /// Identifies the options that change the generated menu, so each
+    /// combination is cached separately.
+    pub fn cache_key(&self) -> String {
+        format!(
+            "{:?}|{:?}|{}|{:?}|{}|{:?}|{:?}",
+            program(),
+            self.config_file,
+            self.format.to_possible_value().unwrap().get_name(),
+            self.submenu,
+            self.lazy,
+            self.static_menu,
+            self.template
+        )
+    }
+
+    pub fn static_menu(&self) -> bool {
+        self.static_menu || self.template.is_some()
+    }
+
+    pub fn template(&self) -> Option<&PathBuf> {
+        self.template.as_ref()
+    }
+
+    pub fn output(&self) -> Option<&PathBuf> {
+        self.output.as_ref()
+    }
+}
+
+/// Path of the running binary for commands run later, e.g. by the window
+/// manager, where `argv[0]` may be relative or not on the `PATH`.
+fn program() -> String {
+    std::env::current_exe()
+        .ok()
+        .map(|path| path.to_string_lossy().into_owned())
+        .or_else(|| std::env::args().next())
+        .unwrap_or_else(|| "box-menu-rs".into())
+}
+
+/// Quotes `arg` for the shell-like parsing window managers apply to
+/// `execute` commands.
+fn shell_quote(arg: &str) -> String {
+    let is_safe = |ch: char| ch.is_ascii_alphanumeric() || "/._-+=:,@%".contains(ch);
+    if !arg.is_empty() && arg.chars().all(is_safe) {
+        arg.to_string()
+    } else {
+        format!("'{}'", arg.replace('\'', "'\\''"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn shell_quote_only_quotes_when_needed() {
+        assert_eq!(
+            shell_quote("Applications/Internet"),
+            "Applications/Internet"
+        );
+        assert_eq!(shell_quote("Web Browsers"), "'Web Browsers'");
+        assert_eq!(shell_quote("Bob's Apps"), "'Bob'\\''s Apps'");
+        assert_eq!(shell_quote(""), "''");
+    }
 }
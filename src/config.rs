@@ -1,6 +1,10 @@
 use crate::menu::MenuNode;
 use serde::{Deserialize, Serialize};
-use std::{collections::HashMap, path::PathBuf};
+use std::{
+    collections::HashMap,
+    env,
+    path::{Path, PathBuf},
+};
 
 #[derive(Serialize, Deserialize)]
 pub struct ConfigCategory {
@@ -22,12 +26,123 @@ pub struct OutputCategory {
     pub icon: Option<String>,
 }
 
+/// Settings for a single desktop entry under `entries`, keyed by its desktop
+/// file id with or without the `.desktop` suffix.
+#[derive(Serialize, Deserialize)]
+pub struct OutputEntry {
+    /// Icon used instead of the ones found along `options.icon_fallback`.
+    pub icon: Option<String>,
+}
+
+/// One step of `options.icon_fallback`, written as its keyword or, for any
+/// other string, a fixed icon name such as `application-x-executable`.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(from = "String", into = "String")]
+pub enum IconFallback {
+    /// The `Icon` key of the desktop entry, or the icon of a category.
+    Icon,
+    /// The `StartupWMClass` key of the desktop entry.
+    StartupWmClass,
+    /// The desktop file id, e.g. `org.gnome.Calculator`.
+    DesktopId,
+    /// The file name of the program started by `Exec`.
+    Exec,
+    /// The icon of the output category the entry is shown in.
+    Category,
+    Named(String),
+}
+
+impl From<String> for IconFallback {
+    fn from(value: String) -> Self {
+        match value.as_str() {
+            "icon" => Self::Icon,
+            "startup_wm_class" => Self::StartupWmClass,
+            "desktop_id" => Self::DesktopId,
+            "exec" => Self::Exec,
+            "category" => Self::Category,
+            _ => Self::Named(value),
+        }
+    }
+}
+
+impl From<IconFallback> for String {
+    fn from(value: IconFallback) -> Self {
+        match value {
+            IconFallback::Icon => "icon".into(),
+            IconFallback::StartupWmClass => "startup_wm_class".into(),
+            IconFallback::DesktopId => "desktop_id".into(),
+            IconFallback::Exec => "exec".into(),
+            IconFallback::Category => "category".into(),
+            IconFallback::Named(name) => name,
+        }
+    }
+}
+
+/// Image format preferred by `options.icon_format` when a theme has both.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum IconFormat {
+    /// PNG before SVG, as most window managers draw them faster.
+    #[default]
+    Raster,
+    /// SVG before PNG, sharp at any size.
+    Svg,
+}
+
+/// Menu levels that get icons, set with `options.icons`. Icons are not
+/// looked up at all for the levels left out.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum IconLevels {
+    /// Categories and entries.
+    #[default]
+    All,
+    /// Only the category menus.
+    Categories,
+    /// Only the program entries.
+    Entries,
+    /// No icons, e.g. for text-only menus or slow machines.
+    None,
+}
+
+impl IconLevels {
+    pub fn categories(self) -> bool {
+        matches!(self, Self::All | Self::Categories)
+    }
+
+    pub fn entries(self) -> bool {
+        matches!(self, Self::All | Self::Entries)
+    }
+}
+
 #[derive(Serialize, Deserialize)]
 #[serde(default)]
 pub struct Options {
     pub visibility_filter: bool,
     pub icon_theme: Option<String>,
+    pub icon_themes: Vec<String>,
     pub category_priority: bool,
+    pub xdg_menu: Option<PathBuf>,
+    pub favorites: Vec<String>,
+    pub icon_fallback: Vec<IconFallback>,
+    pub icon_size: u16,
+    pub icon_scale: u16,
+    pub icon_format: IconFormat,
+    pub icons: IconLevels,
+    pub rasterize_svg: bool,
+    pub validate_icons: bool,
+}
+
+impl Options {
+    /// Icon themes to use in order: `icon_themes`, or else `icon_theme`.
+    /// Empty if the theme should be detected.
+    pub fn configured_themes(&self) -> Vec<String> {
+        if self.icon_themes.is_empty() {
+            self.icon_theme.iter().cloned().collect()
+        } else {
+            self.icon_themes.clone()
+        }
+    }
 }
 
 impl Default for Options {
@@ -35,7 +150,17 @@ impl Default for Options {
         Self {
             visibility_filter: true,
             icon_theme: None,
+            icon_themes: Vec::new(),
             category_priority: false,
+            xdg_menu: None,
+            favorites: Vec::new(),
+            icon_fallback: vec![IconFallback::Icon],
+            icon_size: 24,
+            icon_scale: 1,
+            icon_format: IconFormat::Raster,
+            icons: IconLevels::All,
+            rasterize_svg: false,
+            validate_icons: false,
         }
     }
 }
@@ -44,6 +169,7 @@ impl Default for Options {
 pub struct Config {
     pub category_map: HashMap<String, ConfigCategory>,
     pub output: Option<HashMap<String, OutputCategory>>,
+    pub entries: Option<HashMap<String, OutputEntry>>,
     #[serde(default)]
     pub options: Options,
 }
@@ -58,14 +184,136 @@ impl Config {
         root
     }
 
-    pub fn icon_for_category(&self, category: &str) -> String {
+    /// Icon explicitly configured for an output category.
+    pub fn output_icon(&self, category: &str) -> Option<&str> {
         self.output
             .as_ref()
             .and_then(|output| output.get(category))
-            .and_then(|oc| oc.icon.as_ref())
-            .cloned()
+            .and_then(|oc| oc.icon.as_deref())
+    }
+
+    pub fn icon_for_category(&self, category: &str) -> String {
+        self.output_icon(category)
+            .map(str::to_string)
             .unwrap_or_else(|| format!("applications-{}", category.to_lowercase()))
     }
+
+    /// Icon name of the menu `node` at `path`: the one configured under
+    /// `output`, else the one the menu source provided, else the
+    /// `applications-*` default.
+    pub fn icon_for_menu(&self, path: &str, node: &MenuNode) -> String {
+        self.icon_for_menu_source(path, node.icon())
+    }
+
+    /// Like [`icon_for_menu`](Self::icon_for_menu) with `source_icon` from
+    /// the menu source, for a menu whose node is not built yet.
+    pub fn icon_for_menu_source(&self, path: &str, source_icon: Option<&str>) -> String {
+        self.output_icon(path)
+            .or(source_icon)
+            .map(str::to_string)
+            .unwrap_or_else(|| self.icon_for_category(path))
+    }
+
+    /// Icon configured under `entries` for the desktop file id `id`.
+    pub fn entry_icon(&self, id: &str) -> Option<&str> {
+        self.entries
+            .as_ref()?
+            .iter()
+            .find(|(key, _)| key.strip_suffix(".desktop").unwrap_or(key) == id)?
+            .1
+            .icon
+            .as_deref()
+    }
+
+    /// Expands the icons of `output`, `entries` and `options.icon_fallback`
+    /// with [`expand_icon_path`], relative to `base_dir`.
+    fn expand_icon_paths(&mut self, base_dir: &Path) {
+        let var = |name: &str| env::var(name).ok();
+        let icons = self
+            .output
+            .iter_mut()
+            .flat_map(|output| output.values_mut().map(|category| &mut category.icon))
+            .chain(
+                self.entries
+                    .iter_mut()
+                    .flat_map(|entries| entries.values_mut().map(|entry| &mut entry.icon)),
+            )
+            .flatten();
+        for icon in icons {
+            *icon = expand_icon_path(icon, base_dir, var);
+        }
+        for step in &mut self.options.icon_fallback {
+            if let IconFallback::Named(name) = step {
+                *name = expand_icon_path(name, base_dir, var);
+            }
+        }
+    }
+}
+
+/// Expands a leading `~` and `$VAR` or `${VAR}` in the configured icon
+/// `value`, looking variables up with `var`. A result containing `/`, ending
+/// in an image extension, or naming a file in `base_dir` (the directory of
+/// the config file) is a file path, and a relative one is taken relative to
+/// `base_dir`; anything else stays an icon name.
+pub fn expand_icon_path(
+    value: &str,
+    base_dir: &Path,
+    var: impl Fn(&str) -> Option<String>,
+) -> String {
+    let mut expanded = String::new();
+    let mut rest = value;
+    if let Some(after) = rest.strip_prefix('~')
+        && (after.is_empty() || after.starts_with('/'))
+    {
+        expanded.push_str(&var("HOME").unwrap_or_default());
+        rest = after;
+    }
+    while let Some(start) = rest.find('$') {
+        expanded.push_str(&rest[..start]);
+        let after = &rest[start + 1..];
+        let (name, remainder) = match after.strip_prefix('{') {
+            Some(braced) => match braced.split_once('}') {
+                Some((name, remainder)) => (name, remainder),
+                None => ("", after),
+            },
+            None => {
+                let end = after
+                    .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+                    .unwrap_or(after.len());
+                (&after[..end], &after[end..])
+            }
+        };
+        if name.is_empty() {
+            expanded.push('$');
+        } else {
+            expanded.push_str(&var(name).unwrap_or_default());
+        }
+        rest = remainder;
+    }
+    expanded.push_str(rest);
+
+    let is_path = expanded.contains('/')
+        || has_image_extension(&expanded)
+        || base_dir.join(&expanded).is_file();
+    if is_path && Path::new(&expanded).is_relative() {
+        let relative = expanded.strip_prefix("./").unwrap_or(&expanded);
+        base_dir.join(relative).to_string_lossy().into_owned()
+    } else {
+        expanded
+    }
+}
+
+/// Returns whether `value` ends in the extension of an image format icons
+/// are loaded from.
+fn has_image_extension(value: &str) -> bool {
+    Path::new(value)
+        .extension()
+        .and_then(|extension| extension.to_str())
+        .is_some_and(|extension| {
+            ["png", "svg", "svgz", "xpm"]
+                .iter()
+                .any(|image| extension.eq_ignore_ascii_case(image))
+        })
 }
 
 impl Default for Config {
@@ -102,20 +350,24 @@ impl Default for Config {
         Self {
             category_map: m,
             output: None,
+            entries: None,
             options: Options::default(),
         }
     }
 }
 
 pub fn load_config(config_file: Option<&PathBuf>) -> Result<Config, Box<dyn std::error::Error>> {
-    if let Some(path) = config_file {
+    let (mut cfg, path): (Config, PathBuf) = if let Some(path) = config_file {
         let contents = std::fs::read_to_string(path)?;
-        let cfg: Config = serde_yaml::from_str(&contents)?;
-        Ok(cfg)
+        (serde_yaml::from_str(&contents)?, path.clone())
     } else {
-        let cfg: Config = confy::load("box-menu-rs", "config")?;
-        Ok(cfg)
-    }
+        (
+            confy::load("box-menu-rs", "config")?,
+            confy::get_configuration_file_path("box-menu-rs", "config")?,
+        )
+    };
+    cfg.expand_icon_paths(path.parent().unwrap_or(Path::new("")));
+    Ok(cfg)
 }
 
 #[cfg(test)]
@@ -203,4 +455,164 @@ category_map:
         assert!(!cfg.options.category_priority);
         assert_eq!(cfg.category_map["TestCategory"].priority, None);
     }
+
+    #[test]
+    fn icon_fallback_reads_keywords_and_icon_names() {
+        let options: Options = serde_yaml::from_str(
+            "icon_fallback: [icon, startup_wm_class, exec, application-x-executable]",
+        )
+        .expect("failed to parse options");
+        assert_eq!(
+            options.icon_fallback,
+            [
+                IconFallback::Icon,
+                IconFallback::StartupWmClass,
+                IconFallback::Exec,
+                IconFallback::Named("application-x-executable".into()),
+            ]
+        );
+        assert_eq!(Options::default().icon_fallback, [IconFallback::Icon]);
+    }
+
+    #[test]
+    fn icon_size_scale_and_format_deserialize() {
+        let options: Options =
+            serde_yaml::from_str("icon_size: 48\nicon_scale: 2\nicon_format: svg\n")
+                .expect("failed to parse options");
+        assert_eq!(options.icon_size, 48);
+        assert_eq!(options.icon_scale, 2);
+        assert_eq!(options.icon_format, IconFormat::Svg);
+        assert_eq!(Options::default().icon_format, IconFormat::Raster);
+    }
+
+    #[test]
+    fn icon_levels_select_categories_and_entries() {
+        let options: Options =
+            serde_yaml::from_str("icons: categories\n").expect("failed to parse options");
+        assert_eq!(options.icons, IconLevels::Categories);
+        assert!(options.icons.categories() && !options.icons.entries());
+        assert!(IconLevels::Entries.entries() && !IconLevels::Entries.categories());
+        assert!(!IconLevels::None.entries() && !IconLevels::None.categories());
+        assert_eq!(Options::default().icons, IconLevels::All);
+    }
+
+    #[test]
+    fn expand_icon_path_expands_home_variables_and_relative_paths() {
+        let var = |name: &str| match name {
+            "HOME" => Some("/home/user".to_string()),
+            "DOTFILES" => Some("/home/user/dotfiles".to_string()),
+            _ => None,
+        };
+        let base = Path::new("/etc/box-menu-rs");
+        assert_eq!(
+            expand_icon_path("~/icons/a.png", base, var),
+            "/home/user/icons/a.png"
+        );
+        assert_eq!(
+            expand_icon_path("$DOTFILES/icons/a.png", base, var),
+            "/home/user/dotfiles/icons/a.png"
+        );
+        assert_eq!(
+            expand_icon_path("${DOTFILES}/b.svg", base, var),
+            "/home/user/dotfiles/b.svg"
+        );
+        assert_eq!(
+            expand_icon_path("icons/a.png", base, var),
+            "/etc/box-menu-rs/icons/a.png"
+        );
+        assert_eq!(
+            expand_icon_path("games.png", base, var),
+            "/etc/box-menu-rs/games.png"
+        );
+        assert_eq!(
+            expand_icon_path("logo.SVG", base, var),
+            "/etc/box-menu-rs/logo.SVG"
+        );
+        assert_eq!(expand_icon_path("firefox", base, var), "firefox");
+        assert_eq!(
+            expand_icon_path("org.gnome.Calculator", base, var),
+            "org.gnome.Calculator"
+        );
+        assert_eq!(expand_icon_path("~user", base, var), "~user");
+        assert_eq!(expand_icon_path("a$", base, var), "a$");
+        assert_eq!(expand_icon_path("${UNSET", base, var), "${UNSET");
+    }
+
+    #[test]
+    fn load_config_resolves_icon_paths_and_entry_overrides() {
+        let yaml = r#"
+category_map:
+  Game:
+    output: Games
+output:
+  Games:
+    icon: icons/games.png
+entries:
+  org.example.App.desktop:
+    icon: ./icons/app.png
+  player:
+    icon: multimedia-player
+  editor:
+    icon: editor-icon
+  viewer:
+    icon: viewer.png
+options:
+  icon_fallback: [icon, icons/default.png]
+"#;
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .expect("time went backwards")
+            .as_nanos();
+        let dir = std::env::temp_dir().join(format!("box-menu-rs-test-config-dir-{}", timestamp));
+        fs::create_dir_all(&dir).expect("failed to create temp dir");
+        let config_path = dir.join("config.yml");
+        fs::write(&config_path, yaml).expect("failed to write test config file");
+        fs::write(dir.join("editor-icon"), "").expect("failed to write test icon file");
+
+        let cfg = load_config(Some(&config_path)).expect("failed to load config");
+        let in_dir = |path: &str| dir.join(path).to_string_lossy().into_owned();
+        assert_eq!(
+            cfg.output_icon("Games"),
+            Some(in_dir("icons/games.png").as_str())
+        );
+        assert_eq!(
+            cfg.entry_icon("org.example.App"),
+            Some(in_dir("icons/app.png").as_str())
+        );
+        assert_eq!(cfg.entry_icon("player"), Some("multimedia-player"));
+        assert_eq!(
+            cfg.entry_icon("editor"),
+            Some(in_dir("editor-icon").as_str())
+        );
+        assert_eq!(
+            cfg.entry_icon("viewer"),
+            Some(in_dir("viewer.png").as_str())
+        );
+        assert_eq!(cfg.entry_icon("other"), None);
+        assert_eq!(
+            cfg.options.icon_fallback[1],
+            IconFallback::Named(in_dir("icons/default.png"))
+        );
+
+        fs::remove_dir_all(&dir).expect("failed to remove temp dir");
+    }
+
+    #[test]
+    fn icon_themes_take_precedence_over_icon_theme() {
+        let options: Options = serde_yaml::from_str(
+            "icon_theme: Papirus
+icon_themes: [Adwaita, hicolor]
+",
+        )
+        .expect("failed to parse options");
+        assert_eq!(options.configured_themes(), ["Adwaita", "hicolor"]);
+
+        let options: Options = serde_yaml::from_str(
+            "icon_theme: Papirus
+",
+        )
+        .expect("failed to parse options");
+        assert_eq!(options.configured_themes(), ["Papirus"]);
+        assert!(Options::default().configured_themes().is_empty());
+    }
 }
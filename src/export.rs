@@ -0,0 +1,346 @@
+use crate::{
+    config::{Config, ConfigCategory},
+    escape::escape,
+    menu::MenuNode,
+};
+use std::{
+    collections::BTreeMap,
+    fmt::{self, Write},
+    fs,
+    path::{Path, PathBuf},
+};
+
+pub const MENU_FILE_NAME: &str = "box-menu-applications.menu";
+pub const DIRECTORY_DIR_NAME: &str = "box-menu-directories";
+
+const MENU_DOCTYPE: &str = "<!DOCTYPE Menu PUBLIC \"-//freedesktop//DTD Menu 1.0//EN\"\n \"http://www.freedesktop.org/standards/menu-spec/1.0/menu.dtd\">";
+
+/// Writes `box-menu-applications.menu` and one `.directory` file per output
+/// category into `output_dir` and returns the paths written.
+///
+/// The menu file references the directory files relative to itself, so the
+/// whole export can be moved as one folder. Panels pick it up with
+/// `XDG_MENU_PREFIX=box-menu-` when `output_dir` is in `$XDG_CONFIG_DIRS/menus`.
+pub fn export_xdg_menu(
+    config: &Config,
+    output_dir: &Path,
+) -> Result<Vec<PathBuf>, Box<dyn std::error::Error>> {
+    let directory_dir = output_dir.join(DIRECTORY_DIR_NAME);
+    fs::create_dir_all(&directory_dir)?;
+
+    let root = config.empty_tree();
+    let mut written = Vec::new();
+    let mut directories = Vec::new();
+    collect_paths(&root, "", &mut directories);
+    for path in directories {
+        let directory_path = directory_dir.join(directory_file_name(&path));
+        let label = path.rsplit('/').next().unwrap_or_default();
+        fs::write(
+            &directory_path,
+            directory_entry(label, &config.icon_for_category(&path)),
+        )?;
+        written.push(directory_path);
+    }
+
+    let mut menu = String::new();
+    write_menu(&mut menu, config, &root).expect("writing to a String cannot fail");
+    let menu_path = output_dir.join(MENU_FILE_NAME);
+    fs::write(&menu_path, menu)?;
+    written.push(menu_path);
+
+    Ok(written)
+}
+
+fn collect_paths(node: &MenuNode, path: &str, paths: &mut Vec<String>) {
+    for (child_path, child) in node.children(path) {
+        paths.push(child_path.clone());
+        collect_paths(child, &child_path, paths);
+    }
+}
+
+fn directory_file_name(path: &str) -> String {
+    format!("box-menu-{}.directory", MenuNode::menu_id(path))
+}
+
+fn directory_entry(label: &str, icon: &str) -> String {
+    format!(
+        "[Desktop Entry]\nType=Directory\nName={}\nIcon={}\n",
+        escape_value(label),
+        escape_value(icon)
+    )
+}
+
+/// Desktop Entry string values escape backslashes and control characters.
+fn escape_value(raw: &str) -> String {
+    raw.replace('\\', "\\\\")
+        .replace('\n', "\\n")
+        .replace('\t', "\\t")
+        .replace('\r', "\\r")
+}
+
+/// Categories feeding each output path, as `(category, mapping)` pairs sorted
+/// by category name.
+fn categories_by_output(config: &Config) -> BTreeMap<&str, Vec<(&str, &ConfigCategory)>> {
+    let mut by_output: BTreeMap<&str, Vec<(&str, &ConfigCategory)>> = BTreeMap::new();
+    for (category, mapped) in &config.category_map {
+        let output_name = mapped.output.as_deref().unwrap_or(category);
+        by_output
+            .entry(output_name.trim_matches('/'))
+            .or_default()
+            .push((category, mapped));
+    }
+    for categories in by_output.values_mut() {
+        categories.sort_by_key(|(category, _)| *category);
+    }
+    by_output
+}
+
+fn write_menu<W: Write>(out: &mut W, config: &Config, root: &MenuNode) -> fmt::Result {
+    let by_output = categories_by_output(config);
+    writeln!(out, "{}", MENU_DOCTYPE)?;
+    writeln!(out, "<Menu>")?;
+    writeln!(out, "  <Name>Applications</Name>")?;
+    writeln!(out, "  <DefaultAppDirs/>")?;
+    writeln!(out, "  <DefaultDirectoryDirs/>")?;
+    writeln!(out, "  <DirectoryDir>{}</DirectoryDir>", DIRECTORY_DIR_NAME)?;
+    write_children(out, config, &by_output, root, "", 1)?;
+    writeln!(out, "</Menu>")
+}
+
+fn write_children<W: Write>(
+    out: &mut W,
+    config: &Config,
+    by_output: &BTreeMap<&str, Vec<(&str, &ConfigCategory)>>,
+    node: &MenuNode,
+    path: &str,
+    depth: usize,
+) -> fmt::Result {
+    let indent = "  ".repeat(depth);
+    for (child_path, child) in node.children(path) {
+        writeln!(out, "{}<Menu>", indent)?;
+        writeln!(out, "{}  <Name>{}</Name>", indent, escape(child.label()))?;
+        writeln!(
+            out,
+            "{}  <Directory>{}</Directory>",
+            indent,
+            escape(directory_file_name(&child_path))
+        )?;
+        if let Some(categories) = by_output.get(child_path.as_str()) {
+            write_include(out, config, categories, &format!("{}  ", indent))?;
+        }
+        write_children(out, config, by_output, child, &child_path, depth + 1)?;
+        writeln!(out, "{}</Menu>", indent)?;
+    }
+    Ok(())
+}
+
+/// With `category_priority` an entry only lands in the output of its highest
+/// priority category, so each category excludes the ones that would win.
+fn write_include<W: Write>(
+    out: &mut W,
+    config: &Config,
+    categories: &[(&str, &ConfigCategory)],
+    indent: &str,
+) -> fmt::Result {
+    writeln!(out, "{}<Include>", indent)?;
+    for (category, mapped) in categories {
+        let winners = if config.options.category_priority {
+            outranking_categories(config, category, mapped)
+        } else {
+            Vec::new()
+        };
+
+        if winners.is_empty() {
+            writeln!(
+                out,
+                "{}  <Category>{}</Category>",
+                indent,
+                escape(*category)
+            )?;
+            continue;
+        }
+
+        writeln!(out, "{}  <And>", indent)?;
+        writeln!(
+            out,
+            "{}    <Category>{}</Category>",
+            indent,
+            escape(*category)
+        )?;
+        writeln!(out, "{}    <Not><Or>", indent)?;
+        for winner in winners {
+            writeln!(
+                out,
+                "{}      <Category>{}</Category>",
+                indent,
+                escape(winner)
+            )?;
+        }
+        writeln!(out, "{}    </Or></Not>", indent)?;
+        writeln!(out, "{}  </And>", indent)?;
+    }
+    writeln!(out, "{}</Include>", indent)
+}
+
+/// Categories mapped to a different output that beat `category` under the
+/// same ordering `run()` uses: higher priority, then greater output name.
+fn outranking_categories<'a>(
+    config: &'a Config,
+    category: &str,
+    mapped: &ConfigCategory,
+) -> Vec<&'a str> {
+    let output_name = mapped.output.as_deref().unwrap_or(category);
+    let rank = (mapped.priority.unwrap_or(0), output_name);
+    let mut winners: Vec<&str> = config
+        .category_map
+        .iter()
+        .filter(|(other, other_mapped)| {
+            let other_output = other_mapped.output.as_deref().unwrap_or(other);
+            other_output != output_name && (other_mapped.priority.unwrap_or(0), other_output) > rank
+        })
+        .map(|(other, _)| other.as_str())
+        .collect();
+    winners.sort();
+    winners
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::OutputCategory;
+    use std::collections::HashMap;
+    use std::time::{SystemTime, UNIX_EPOCH};
+
+    fn test_config(category_priority: bool) -> Config {
+        let mut category_map = HashMap::new();
+        category_map.insert(
+            "AudioVideo".to_string(),
+            ConfigCategory {
+                output: Some("Apps/Multimedia".into()),
+                priority: Some(10),
+            },
+        );
+        category_map.insert(
+            "Audio".to_string(),
+            ConfigCategory::default("Apps/Multimedia".into()),
+        );
+        category_map.insert(
+            "Graphics".to_string(),
+            ConfigCategory::default("Graphics".into()),
+        );
+        let mut output = HashMap::new();
+        output.insert(
+            "Apps/Multimedia".to_string(),
+            OutputCategory {
+                icon: Some("applications-multimedia".into()),
+            },
+        );
+        let mut cfg = Config {
+            category_map,
+            output: Some(output),
+            entries: None,
+            options: Default::default(),
+        };
+        cfg.options.category_priority = category_priority;
+        cfg
+    }
+
+    #[test]
+    fn write_menu_golden() {
+        let config = test_config(false);
+        let mut out = String::new();
+        write_menu(&mut out, &config, &config.empty_tree()).unwrap();
+
+        assert_eq!(
+            out,
+            format!(
+                "{}\n\
+                 <Menu>\n\
+                 \x20 <Name>Applications</Name>\n\
+                 \x20 <DefaultAppDirs/>\n\
+                 \x20 <DefaultDirectoryDirs/>\n\
+                 \x20 <DirectoryDir>box-menu-directories</DirectoryDir>\n\
+                 \x20 <Menu>\n\
+                 \x20   <Name>Apps</Name>\n\
+                 \x20   <Directory>box-menu-Apps.directory</Directory>\n\
+                 \x20   <Menu>\n\
+                 \x20     <Name>Multimedia</Name>\n\
+                 \x20     <Directory>box-menu-Apps-Multimedia.directory</Directory>\n\
+                 \x20     <Include>\n\
+                 \x20       <Category>Audio</Category>\n\
+                 \x20       <Category>AudioVideo</Category>\n\
+                 \x20     </Include>\n\
+                 \x20   </Menu>\n\
+                 \x20 </Menu>\n\
+                 \x20 <Menu>\n\
+                 \x20   <Name>Graphics</Name>\n\
+                 \x20   <Directory>box-menu-Graphics.directory</Directory>\n\
+                 \x20   <Include>\n\
+                 \x20     <Category>Graphics</Category>\n\
+                 \x20   </Include>\n\
+                 \x20 </Menu>\n\
+                 </Menu>\n",
+                MENU_DOCTYPE
+            )
+        );
+    }
+
+    #[test]
+    fn category_priority_excludes_winning_categories() {
+        let config = test_config(true);
+        let mut out = String::new();
+        write_menu(&mut out, &config, &config.empty_tree()).unwrap();
+
+        // Graphics loses against the higher priority AudioVideo, and Audio
+        // loses the tie against Graphics by output name.
+        assert!(out.contains(
+            "    <Include>\n\
+             \x20     <And>\n\
+             \x20       <Category>Graphics</Category>\n\
+             \x20       <Not><Or>\n\
+             \x20         <Category>AudioVideo</Category>\n\
+             \x20       </Or></Not>\n\
+             \x20     </And>\n\
+             \x20   </Include>\n"
+        ));
+        assert!(out.contains(
+            "      <Include>\n\
+             \x20       <And>\n\
+             \x20         <Category>Audio</Category>\n\
+             \x20         <Not><Or>\n\
+             \x20           <Category>Graphics</Category>\n\
+             \x20         </Or></Not>\n\
+             \x20       </And>\n\
+             \x20       <Category>AudioVideo</Category>\n\
+             \x20     </Include>\n"
+        ));
+    }
+
+    #[test]
+    fn export_writes_menu_and_directory_files() {
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .expect("time went backwards")
+            .as_nanos();
+        let output_dir =
+            std::env::temp_dir().join(format!("box-menu-rs-test-export-{}", timestamp));
+
+        let config = test_config(false);
+        let written = export_xdg_menu(&config, &output_dir).expect("export failed");
+        assert_eq!(written.len(), 4);
+
+        let directory = fs::read_to_string(
+            output_dir
+                .join(DIRECTORY_DIR_NAME)
+                .join("box-menu-Apps-Multimedia.directory"),
+        )
+        .expect("missing directory file");
+        assert_eq!(
+            directory,
+            "[Desktop Entry]\nType=Directory\nName=Multimedia\nIcon=applications-multimedia\n"
+        );
+        assert!(output_dir.join(MENU_FILE_NAME).is_file());
+
+        fs::remove_dir_all(&output_dir).expect("failed to remove export dir");
+    }
+}
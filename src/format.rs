@@ -0,0 +1,243 @@
+use crate::{
+    config::Config,
+    icon::{IconResolver, resolve_category_icon},
+    menu::{Entry, MenuNode},
+};
+use clap::ValueEnum;
+use std::path::PathBuf;
+
+mod fluxbox;
+mod fvwm;
+mod icewm;
+mod jwm;
+mod openbox;
+mod pekwm;
+
+/// Window manager syntax written by [`render`].
+#[derive(Copy, Clone, Debug, ValueEnum)]
+#[value(rename_all = "kebab-case")]
+pub enum OutputFormat {
+    #[value(help = "Openbox/labwc pipemenu XML.")]
+    Openbox,
+    #[value(help = "Fluxbox menu syntax for [include].")]
+    Fluxbox,
+    #[value(help = "JWM menu XML for <Include>exec:...</Include>.")]
+    Jwm,
+    #[value(help = "IceWM menu syntax for menuprog.")]
+    Icewm,
+    #[value(help = "Fvwm3 DestroyMenu/AddToMenu commands for PipeRead.")]
+    Fvwm,
+    #[value(help = "pekwm dynamic menu.")]
+    Pekwm,
+}
+
+/// Renders the menu tree in the requested window manager syntax.
+///
+/// `root` is the node at the slash-separated `path` (empty for the whole
+/// tree); only its content is written, not a wrapper for the node itself.
+/// `excluded` holds `(label, reason)` pairs of entries dropped by visibility
+/// filtering; writers that support comments list them after the menu.
+pub fn render(
+    format: OutputFormat,
+    root: &MenuNode,
+    path: &str,
+    config: &Config,
+    icons: &dyn IconResolver,
+    excluded: &[(String, String)],
+) -> String {
+    let icons = &CategoryIcons::new(config, icons);
+    let mut out = String::new();
+    let result = match format {
+        OutputFormat::Openbox => openbox::write_menu(&mut out, root, path, icons, excluded),
+        OutputFormat::Fluxbox => fluxbox::write_menu(&mut out, root, path, icons, excluded),
+        OutputFormat::Jwm => jwm::write_menu(&mut out, root, path, icons, excluded),
+        OutputFormat::Icewm => icewm::write_menu(&mut out, root, path, icons, excluded),
+        OutputFormat::Fvwm => fvwm::write_menu(&mut out, root, path, icons, excluded),
+        OutputFormat::Pekwm => pekwm::write_menu(&mut out, root, path, icons, excluded),
+    };
+    result.expect("writing to a String cannot fail");
+    out
+}
+
+/// Renders an Openbox pipemenu whose submenus are stubs executing
+/// `submenu_command(path)`, see [`openbox::write_lazy_menu`].
+pub fn render_lazy(
+    root: &MenuNode,
+    path: &str,
+    config: &Config,
+    icons: &dyn IconResolver,
+    excluded: &[(String, String)],
+    submenu_command: &dyn Fn(&str) -> String,
+) -> String {
+    let icons = &CategoryIcons::new(config, icons);
+    let mut out = String::new();
+    openbox::write_lazy_menu(&mut out, root, path, icons, excluded, submenu_command)
+        .expect("writing to a String cannot fail");
+    out
+}
+
+/// Renders a complete Openbox/labwc `menu.xml` from `template`, see
+/// [`openbox::write_static`].
+pub fn render_static(
+    root: &MenuNode,
+    config: &Config,
+    icons: &dyn IconResolver,
+    template: Option<&str>,
+    favorites: &[Entry],
+) -> Result<String, Box<dyn std::error::Error>> {
+    if let Some(template) = template
+        && !template.contains(openbox::APPS_PLACEHOLDER)
+    {
+        return Err(format!(
+            "menu template does not contain the {} placeholder",
+            openbox::APPS_PLACEHOLDER
+        )
+        .into());
+    }
+
+    let mut out = String::new();
+    openbox::write_static(
+        &mut out,
+        root,
+        &CategoryIcons::new(config, icons),
+        template,
+        favorites,
+    )
+    .expect("writing to a String cannot fail");
+    Ok(out)
+}
+
+/// Icons of the output categories, as configured and found by a resolver.
+struct CategoryIcons<'a> {
+    config: &'a Config,
+    resolver: &'a dyn IconResolver,
+}
+
+impl<'a> CategoryIcons<'a> {
+    fn new(config: &'a Config, resolver: &'a dyn IconResolver) -> Self {
+        Self { config, resolver }
+    }
+
+    /// Resolves the icon of the output category at `path`.
+    ///
+    /// An icon configured under `output` wins over the one the menu source
+    /// provided for `node`, which in turn wins over the `applications-*`
+    /// default. If none of these is found, the fixed names of
+    /// `options.icon_fallback` are tried. Nothing is looked up if
+    /// `options.icons` leaves out category icons.
+    fn get(&self, path: &str, node: &MenuNode) -> Option<PathBuf> {
+        if !self.config.options.icons.categories() {
+            return None;
+        }
+        let icon_name = self.config.icon_for_menu(path, node);
+        resolve_category_icon(
+            &icon_name,
+            &self.config.options.icon_fallback,
+            self.resolver,
+        )
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod test_support {
+    use crate::{
+        config::{Config, ConfigCategory, OutputCategory},
+        icon::DirectoryIcons,
+        menu::{Entry, MenuNode},
+    };
+    use std::{collections::HashMap, fs, path::PathBuf};
+
+    /// Icon file used for the `Graphics` output category in golden tests.
+    pub fn category_icon_path() -> PathBuf {
+        let path = std::env::temp_dir().join("box-menu-rs-format-test-graphics.png");
+        fs::write(&path, b"test").expect("failed to write test icon");
+        path
+    }
+
+    /// Resolver that only accepts existing icon paths, independent of the
+    /// icon themes installed on the host.
+    pub fn icons() -> DirectoryIcons {
+        DirectoryIcons::new(std::env::temp_dir().join("box-menu-rs-format-test-icons"))
+    }
+
+    /// A small tree with one top-level and one nested category.
+    pub fn sample_menu() -> (Config, MenuNode) {
+        let mut category_map = HashMap::new();
+        category_map.insert(
+            "Graphics".to_string(),
+            ConfigCategory::default("Graphics".into()),
+        );
+        category_map.insert(
+            "Office".to_string(),
+            ConfigCategory::default("Apps/Office".into()),
+        );
+        let mut output = HashMap::new();
+        output.insert(
+            "Graphics".to_string(),
+            OutputCategory {
+                icon: Some(category_icon_path().to_string_lossy().into_owned()),
+            },
+        );
+        for path in ["Apps", "Apps/Office"] {
+            output.insert(
+                path.to_string(),
+                OutputCategory {
+                    icon: Some("box-menu-rs-test-missing-icon".into()),
+                },
+            );
+        }
+        let config = Config {
+            category_map,
+            output: Some(output),
+            entries: None,
+            options: Default::default(),
+        };
+
+        let mut root = config.empty_tree();
+        root.insert(
+            "Graphics",
+            Entry {
+                id: "draw".into(),
+                label: "Draw (beta) & \"co\"".into(),
+                exec: "draw --title {x}".into(),
+                icon: Some(PathBuf::from("/icons/draw.png")),
+            },
+        );
+        root.insert(
+            "Graphics",
+            Entry {
+                id: "viewer".into(),
+                label: "Viewer".into(),
+                exec: "viewer %f".into(),
+                icon: None,
+            },
+        );
+        root.insert(
+            "Apps/Office",
+            Entry {
+                id: "writer".into(),
+                label: "Writer".into(),
+                exec: "writer".into(),
+                icon: None,
+            },
+        );
+        (config, root)
+    }
+
+    pub fn excluded() -> Vec<(String, String)> {
+        vec![("Hidden App".into(), "NoDisplay=true".into())]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::format::test_support::{icons, sample_menu};
+
+    #[test]
+    fn render_static_rejects_template_without_apps() {
+        let (config, root) = sample_menu();
+        let result = render_static(&root, &config, &icons(), Some("<openbox_menu/>"), &[]);
+        assert!(result.is_err());
+    }
+}
@@ -0,0 +1,129 @@
+use super::CategoryIcons;
+use crate::menu::{Entry, MenuItem, MenuNode};
+use std::{
+    fmt::{self, Write},
+    path::Path,
+};
+
+/// Writes the menu in Fluxbox syntax, suitable for `[include]` from the
+/// user's `~/.fluxbox/menu`.
+pub fn write_menu<W: Write>(
+    out: &mut W,
+    root: &MenuNode,
+    path: &str,
+    icons: &CategoryIcons,
+    excluded: &[(String, String)],
+) -> fmt::Result {
+    write_items(out, root, icons, path)?;
+
+    if !excluded.is_empty() {
+        writeln!(out, "# Excluded entries (visibility filtering):")?;
+        for (label, reason) in excluded {
+            writeln!(out, "#   {} ({})", label, reason)?;
+        }
+    }
+    Ok(())
+}
+
+fn write_node<W: Write>(
+    out: &mut W,
+    node: &MenuNode,
+    icons: &CategoryIcons,
+    path: &str,
+) -> fmt::Result {
+    writeln!(
+        out,
+        "[submenu] ({}){}",
+        escape(node.label()),
+        icon_suffix(icons.get(path, node).as_deref())
+    )?;
+    write_items(out, node, icons, path)?;
+    writeln!(out, "[end]")
+}
+
+fn write_items<W: Write>(
+    out: &mut W,
+    node: &MenuNode,
+    icons: &CategoryIcons,
+    path: &str,
+) -> fmt::Result {
+    for item in node.items(path) {
+        match item {
+            MenuItem::Menu(child_path, child) => write_node(out, child, icons, &child_path)?,
+            MenuItem::Entry(entry) => write_entry(out, entry)?,
+            MenuItem::Separator => writeln!(out, "[separator]")?,
+        }
+    }
+    Ok(())
+}
+
+fn write_entry<W: Write>(out: &mut W, entry: &Entry) -> fmt::Result {
+    writeln!(
+        out,
+        "[exec] ({}) {{{}}}{}",
+        escape(&entry.label),
+        escape(&entry.exec),
+        icon_suffix(entry.icon.as_deref())
+    )
+}
+
+fn icon_suffix(icon: Option<&Path>) -> String {
+    icon.map(|p| format!(" <{}>", escape(&p.to_string_lossy())))
+        .unwrap_or_default()
+}
+
+/// Fluxbox reads `(label)`, `{command}` and `<icon>` up to the matching
+/// closing delimiter and skips characters preceded by a backslash.
+fn escape(raw: &str) -> String {
+    let mut escaped = String::with_capacity(raw.len());
+    for ch in raw.chars() {
+        if matches!(ch, '(' | ')' | '{' | '}' | '<' | '>' | '\\') {
+            escaped.push('\\');
+        }
+        escaped.push(ch);
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::format::test_support::{category_icon_path, excluded, icons, sample_menu};
+
+    #[test]
+    fn escape_delimiters() {
+        assert_eq!(escape("plain"), "plain");
+        assert_eq!(escape("GIMP (2.10)"), "GIMP \\(2.10\\)");
+        assert_eq!(escape("{a} <b> \\"), "\\{a\\} \\<b\\> \\\\");
+    }
+
+    #[test]
+    fn write_menu_golden() {
+        let (config, root) = sample_menu();
+        let mut out = String::new();
+        write_menu(
+            &mut out,
+            &root,
+            "",
+            &CategoryIcons::new(&config, &icons()),
+            &excluded(),
+        )
+        .unwrap();
+
+        let expected = format!(
+            "[submenu] (Apps)\n\
+             [submenu] (Office)\n\
+             [exec] (Writer) {{writer}}\n\
+             [end]\n\
+             [end]\n\
+             [submenu] (Graphics) <{}>\n\
+             [exec] (Draw \\(beta\\) & \"co\") {{draw --title \\{{x\\}}}} </icons/draw.png>\n\
+             [exec] (Viewer) {{viewer %f}}\n\
+             [end]\n\
+             # Excluded entries (visibility filtering):\n\
+             #   Hidden App (NoDisplay=true)\n",
+            category_icon_path().display()
+        );
+        assert_eq!(out, expected);
+    }
+}
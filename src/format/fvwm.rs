@@ -0,0 +1,159 @@
+use super::CategoryIcons;
+use crate::menu::{MenuItem, MenuNode};
+use std::{
+    fmt::{self, Write},
+    path::Path,
+};
+
+/// Name of the top-level Fvwm menu the generated output fills in.
+const ROOT_MENU: &str = "BoxMenu";
+
+/// Writes Fvwm3 commands for `PipeRead 'box-menu-rs --format fvwm'`.
+///
+/// The root menu is only emptied (`DestroyMenu recreate`) so that a
+/// `DynamicPopupAction` configured on it survives regeneration.
+pub fn write_menu<W: Write>(
+    out: &mut W,
+    root: &MenuNode,
+    path: &str,
+    icons: &CategoryIcons,
+    excluded: &[(String, String)],
+) -> fmt::Result {
+    if !excluded.is_empty() {
+        writeln!(out, "# Excluded entries (visibility filtering):")?;
+        for (label, reason) in excluded {
+            writeln!(out, "#   {} ({})", label, reason)?;
+        }
+    }
+
+    writeln!(out, "DestroyMenu recreate {}", menu_name(path))?;
+    write_items(out, root, icons, path)
+}
+
+fn write_node<W: Write>(
+    out: &mut W,
+    node: &MenuNode,
+    icons: &CategoryIcons,
+    path: &str,
+) -> fmt::Result {
+    writeln!(out, "DestroyMenu {}", menu_name(path))?;
+    write_items(out, node, icons, path)
+}
+
+fn write_items<W: Write>(
+    out: &mut W,
+    node: &MenuNode,
+    icons: &CategoryIcons,
+    path: &str,
+) -> fmt::Result {
+    writeln!(out, "AddToMenu {}", menu_name(path))?;
+
+    let items = node.items(path);
+    for item in &items {
+        match item {
+            MenuItem::Menu(child_path, child) => writeln!(
+                out,
+                "+ \"{}{}\" Popup {}",
+                escape(child.label()),
+                icon_suffix(icons.get(child_path, child).as_deref()),
+                menu_name(child_path)
+            )?,
+            MenuItem::Entry(entry) => writeln!(
+                out,
+                "+ \"{}{}\" Exec exec {}",
+                escape(&entry.label),
+                icon_suffix(entry.icon.as_deref()),
+                entry.exec.replace('$', "$$")
+            )?,
+            MenuItem::Separator => writeln!(out, "+ \"\" Nop")?,
+        }
+    }
+
+    for item in items {
+        if let MenuItem::Menu(child_path, child) = item {
+            write_node(out, child, icons, &child_path)?;
+        }
+    }
+    Ok(())
+}
+
+fn menu_name(path: &str) -> String {
+    if path.is_empty() {
+        ROOT_MENU.to_string()
+    } else {
+        format!("{}-{}", ROOT_MENU, MenuNode::menu_id(path))
+    }
+}
+
+fn icon_suffix(icon: Option<&Path>) -> String {
+    icon.map(|p| format!("%{}%", p.display()))
+        .unwrap_or_default()
+}
+
+/// Fvwm treats `&` as hotkey marker, `%` and `*` as picture delimiters and
+/// `$` as variable prefix inside menu labels; doubling yields the literal.
+fn escape(raw: &str) -> String {
+    let mut escaped = String::with_capacity(raw.len());
+    for ch in raw.chars() {
+        match ch {
+            '&' | '%' | '*' | '$' => {
+                escaped.push(ch);
+                escaped.push(ch);
+            }
+            '"' | '\\' => {
+                escaped.push('\\');
+                escaped.push(ch);
+            }
+            _ => escaped.push(ch),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::format::test_support::{category_icon_path, excluded, icons, sample_menu};
+
+    #[test]
+    fn escape_label_markers() {
+        assert_eq!(escape("plain"), "plain");
+        assert_eq!(escape("R&D 100% *new*"), "R&&D 100%% **new**");
+        assert_eq!(escape("say \"$HOME\""), "say \\\"$$HOME\\\"");
+    }
+
+    #[test]
+    fn write_menu_golden() {
+        let (config, root) = sample_menu();
+        let mut out = String::new();
+        write_menu(
+            &mut out,
+            &root,
+            "",
+            &CategoryIcons::new(&config, &icons()),
+            &excluded(),
+        )
+        .unwrap();
+
+        let expected = format!(
+            "# Excluded entries (visibility filtering):\n\
+             #   Hidden App (NoDisplay=true)\n\
+             DestroyMenu recreate BoxMenu\n\
+             AddToMenu BoxMenu\n\
+             + \"Apps\" Popup BoxMenu-Apps\n\
+             + \"Graphics%{}%\" Popup BoxMenu-Graphics\n\
+             DestroyMenu BoxMenu-Apps\n\
+             AddToMenu BoxMenu-Apps\n\
+             + \"Office\" Popup BoxMenu-Apps-Office\n\
+             DestroyMenu BoxMenu-Apps-Office\n\
+             AddToMenu BoxMenu-Apps-Office\n\
+             + \"Writer\" Exec exec writer\n\
+             DestroyMenu BoxMenu-Graphics\n\
+             AddToMenu BoxMenu-Graphics\n\
+             + \"Draw (beta) && \\\"co\\\"%/icons/draw.png%\" Exec exec draw --title {{x}}\n\
+             + \"Viewer\" Exec exec viewer %f\n",
+            category_icon_path().display()
+        );
+        assert_eq!(out, expected);
+    }
+}
@@ -0,0 +1,145 @@
+use super::CategoryIcons;
+use crate::menu::{Entry, MenuItem, MenuNode};
+use std::{
+    fmt::{self, Write},
+    path::Path,
+};
+
+/// IceWM placeholder for "no icon" in `menu` and `prog` lines.
+const NO_ICON: &str = "-";
+
+/// Writes the menu in IceWM syntax, e.g. for `menuprog "Apps" - box-menu-rs --format icewm`.
+pub fn write_menu<W: Write>(
+    out: &mut W,
+    root: &MenuNode,
+    path: &str,
+    icons: &CategoryIcons,
+    excluded: &[(String, String)],
+) -> fmt::Result {
+    write_items(out, root, icons, path, 0)?;
+
+    if !excluded.is_empty() {
+        writeln!(out, "# Excluded entries (visibility filtering):")?;
+        for (label, reason) in excluded {
+            writeln!(out, "#   {} ({})", label, reason)?;
+        }
+    }
+    Ok(())
+}
+
+fn write_node<W: Write>(
+    out: &mut W,
+    node: &MenuNode,
+    icons: &CategoryIcons,
+    path: &str,
+    depth: usize,
+) -> fmt::Result {
+    writeln!(
+        out,
+        "{}menu {} {} {{",
+        indent(depth),
+        quote(node.label()),
+        icon_field(icons.get(path, node).as_deref())
+    )?;
+    write_items(out, node, icons, path, depth + 1)?;
+    writeln!(out, "{}}}", indent(depth))
+}
+
+fn write_items<W: Write>(
+    out: &mut W,
+    node: &MenuNode,
+    icons: &CategoryIcons,
+    path: &str,
+    depth: usize,
+) -> fmt::Result {
+    for item in node.items(path) {
+        match item {
+            MenuItem::Menu(child_path, child) => write_node(out, child, icons, &child_path, depth)?,
+            MenuItem::Entry(entry) => write_entry(out, entry, depth)?,
+            MenuItem::Separator => writeln!(out, "{}separator", indent(depth))?,
+        }
+    }
+    Ok(())
+}
+
+fn write_entry<W: Write>(out: &mut W, entry: &Entry, depth: usize) -> fmt::Result {
+    writeln!(
+        out,
+        "{}prog {} {} {}",
+        indent(depth),
+        quote(&entry.label),
+        icon_field(entry.icon.as_deref()),
+        entry.exec.replace('\n', " ")
+    )
+}
+
+fn indent(depth: usize) -> String {
+    "    ".repeat(depth)
+}
+
+fn icon_field(icon: Option<&Path>) -> String {
+    icon.map(|p| quote(&p.to_string_lossy()))
+        .unwrap_or_else(|| NO_ICON.to_string())
+}
+
+/// IceWM reads double-quoted words and honours backslash escapes inside
+/// them; newlines would end the menu line, so they are folded into spaces.
+fn quote(raw: &str) -> String {
+    let mut quoted = String::with_capacity(raw.len() + 2);
+    quoted.push('"');
+    for ch in raw.chars() {
+        match ch {
+            '"' | '\\' => {
+                quoted.push('\\');
+                quoted.push(ch);
+            }
+            '\n' | '\r' => quoted.push(' '),
+            _ => quoted.push(ch),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::format::test_support::{category_icon_path, excluded, icons, sample_menu};
+
+    #[test]
+    fn quote_escapes_quotes_and_backslashes() {
+        assert_eq!(quote("plain"), "\"plain\"");
+        assert_eq!(quote("say \"hi\" \\o/"), "\"say \\\"hi\\\" \\\\o/\"");
+        assert_eq!(quote("two\nlines"), "\"two lines\"");
+    }
+
+    #[test]
+    fn write_menu_golden() {
+        let (config, root) = sample_menu();
+        let mut out = String::new();
+        write_menu(
+            &mut out,
+            &root,
+            "",
+            &CategoryIcons::new(&config, &icons()),
+            &excluded(),
+        )
+        .unwrap();
+
+        let expected = format!(
+            "menu \"Apps\" - {{\n\
+             \x20   menu \"Office\" - {{\n\
+             \x20       prog \"Writer\" - writer\n\
+             \x20   }}\n\
+             }}\n\
+             menu \"Graphics\" \"{}\" {{\n\
+             \x20   prog \"Draw (beta) & \\\"co\\\"\" \"/icons/draw.png\" draw --title {{x}}\n\
+             \x20   prog \"Viewer\" - viewer %f\n\
+             }}\n\
+             # Excluded entries (visibility filtering):\n\
+             #   Hidden App (NoDisplay=true)\n",
+            category_icon_path().display()
+        );
+        assert_eq!(out, expected);
+    }
+}
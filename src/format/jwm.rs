@@ -0,0 +1,122 @@
+use super::CategoryIcons;
+use crate::{
+    escape::escape,
+    menu::{Entry, MenuItem, MenuNode},
+};
+use std::{
+    fmt::{self, Write},
+    path::Path,
+};
+
+/// Writes a JWM document for `<Include>exec:box-menu-rs --format jwm</Include>`.
+pub fn write_menu<W: Write>(
+    out: &mut W,
+    root: &MenuNode,
+    path: &str,
+    icons: &CategoryIcons,
+    excluded: &[(String, String)],
+) -> fmt::Result {
+    writeln!(out, "<?xml version=\"1.0\" encoding=\"UTF-8\"?>")?;
+    writeln!(out, "<JWM>")?;
+    write_items(out, root, icons, path)?;
+    writeln!(out, "</JWM>")?;
+
+    if !excluded.is_empty() {
+        writeln!(out, "<!-- Excluded entries (visibility filtering):")?;
+        for (label, reason) in excluded {
+            let comment_line = format!("  {} ({})", label, reason).replace("--", "—");
+            writeln!(out, "{}", comment_line)?;
+        }
+        writeln!(out, "-->")?;
+    }
+    Ok(())
+}
+
+fn write_node<W: Write>(
+    out: &mut W,
+    node: &MenuNode,
+    icons: &CategoryIcons,
+    path: &str,
+) -> fmt::Result {
+    writeln!(
+        out,
+        "<Menu label=\"{}\"{}>",
+        escape(node.label()),
+        icon_attr(icons.get(path, node).as_deref())
+    )?;
+    write_items(out, node, icons, path)?;
+    writeln!(out, "</Menu>")
+}
+
+fn write_items<W: Write>(
+    out: &mut W,
+    node: &MenuNode,
+    icons: &CategoryIcons,
+    path: &str,
+) -> fmt::Result {
+    for item in node.items(path) {
+        match item {
+            MenuItem::Menu(child_path, child) => write_node(out, child, icons, &child_path)?,
+            MenuItem::Entry(entry) => write_entry(out, entry)?,
+            MenuItem::Separator => writeln!(out, "<Separator/>")?,
+        }
+    }
+    Ok(())
+}
+
+fn write_entry<W: Write>(out: &mut W, entry: &Entry) -> fmt::Result {
+    // Unlike the Openbox writer the command is element text, so it is escaped
+    // as well; JWM rejects the whole include on malformed XML.
+    writeln!(
+        out,
+        "<Program label=\"{}\"{}>{}</Program>",
+        escape(entry.label.as_str()),
+        icon_attr(entry.icon.as_deref()),
+        escape(entry.exec.as_str())
+    )
+}
+
+fn icon_attr(icon: Option<&Path>) -> String {
+    icon.map(|p| format!(" icon=\"{}\"", escape(p.to_string_lossy())))
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::format::test_support::{category_icon_path, excluded, icons, sample_menu};
+
+    #[test]
+    fn write_menu_golden() {
+        let (config, root) = sample_menu();
+        let mut out = String::new();
+        write_menu(
+            &mut out,
+            &root,
+            "",
+            &CategoryIcons::new(&config, &icons()),
+            &excluded(),
+        )
+        .unwrap();
+
+        let expected = format!(
+            "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+             <JWM>\n\
+             <Menu label=\"Apps\">\n\
+             <Menu label=\"Office\">\n\
+             <Program label=\"Writer\">writer</Program>\n\
+             </Menu>\n\
+             </Menu>\n\
+             <Menu label=\"Graphics\" icon=\"{}\">\n\
+             <Program label=\"Draw (beta) &amp; &quot;co&quot;\" icon=\"/icons/draw.png\">draw --title {{x}}</Program>\n\
+             <Program label=\"Viewer\">viewer %f</Program>\n\
+             </Menu>\n\
+             </JWM>\n\
+             <!-- Excluded entries (visibility filtering):\n  \
+             Hidden App (NoDisplay=true)\n\
+             -->\n",
+            category_icon_path().display()
+        );
+        assert_eq!(out, expected);
+    }
+}
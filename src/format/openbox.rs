@@ -0,0 +1,307 @@
+use super::CategoryIcons;
+use crate::{
+    escape::escape,
+    menu::{Entry, MenuItem, MenuNode},
+};
+use std::{
+    fmt::{self, Write},
+    path::Path,
+};
+
+const OPENBOX_XMLNS: &str = "http://openbox.org/";
+const OPENBOX_XSI: &str = "http://www.w3.org/2001/XMLSchema-instance";
+
+/// Placeholder replaced by the generated category menus in static templates.
+pub const APPS_PLACEHOLDER: &str = "{{apps}}";
+/// Placeholder replaced by the configured favorite entries.
+pub const FAVORITES_PLACEHOLDER: &str = "{{favorites}}";
+
+/// Template used for static menus when the user does not supply one.
+const DEFAULT_TEMPLATE: &str = "<?xml version=\"1.0\" encoding=\"UTF-8\"?>
+<openbox_menu xmlns=\"http://openbox.org/\">
+<menu id=\"root-menu\" label=\"\">
+{{favorites}}{{apps}}</menu>
+</openbox_menu>
+";
+
+pub fn write_menu<W: Write>(
+    out: &mut W,
+    root: &MenuNode,
+    path: &str,
+    icons: &CategoryIcons,
+    excluded: &[(String, String)],
+) -> fmt::Result {
+    write_document(out, excluded, |out| write_items(out, root, icons, path))
+}
+
+/// Like [`write_menu`], but submenus are emitted as pipemenu stubs running
+/// `submenu_command(path)`, so their content is only generated when opened.
+pub fn write_lazy_menu<W: Write>(
+    out: &mut W,
+    root: &MenuNode,
+    path: &str,
+    icons: &CategoryIcons,
+    excluded: &[(String, String)],
+    submenu_command: &dyn Fn(&str) -> String,
+) -> fmt::Result {
+    write_document(out, excluded, |out| {
+        for item in root.items(path) {
+            match item {
+                MenuItem::Menu(child_path, child) => writeln!(
+                    out,
+                    "<menu id=\"boxmenu-{}\" label=\"{}\"{} execute=\"{}\" />",
+                    MenuNode::menu_id(&child_path),
+                    escape(child.label()),
+                    icon_attr(icons.get(&child_path, child).as_deref()),
+                    escape(submenu_command(&child_path))
+                )?,
+                MenuItem::Entry(entry) => write_entry(out, entry)?,
+                MenuItem::Separator => writeln!(out, "<separator />")?,
+            }
+        }
+        Ok(())
+    })
+}
+
+fn write_document<W: Write>(
+    out: &mut W,
+    excluded: &[(String, String)],
+    write_body: impl FnOnce(&mut W) -> fmt::Result,
+) -> fmt::Result {
+    writeln!(out, "<?xml version=\"1.0\" encoding=\"UTF-8\"?>")?;
+    writeln!(
+        out,
+        "<openbox_menu xmlns=\"{}\" xmlns:xsi=\"{}\" xsi:schemaLocation=\"{}\" >",
+        OPENBOX_XMLNS, OPENBOX_XSI, OPENBOX_XMLNS
+    )?;
+    write_body(out)?;
+    writeln!(out, "</openbox_menu>")?;
+
+    if !excluded.is_empty() {
+        writeln!(out, "<!-- Excluded entries (visibility filtering):")?;
+        for (label, reason) in excluded {
+            let comment_line = format!("  {} ({})", label, reason).replace("--", "—");
+            writeln!(out, "{}", comment_line)?;
+        }
+        writeln!(out, "-->")?;
+    }
+    Ok(())
+}
+
+/// Writes a complete `menu.xml` by filling the placeholders of `template`
+/// (or a minimal `root-menu` wrapper) with the generated menus.
+pub fn write_static<W: Write>(
+    out: &mut W,
+    root: &MenuNode,
+    icons: &CategoryIcons,
+    template: Option<&str>,
+    favorites: &[Entry],
+) -> fmt::Result {
+    let template = template.unwrap_or(DEFAULT_TEMPLATE);
+
+    let mut apps = String::new();
+    write_items(&mut apps, root, icons, "")?;
+    let mut favorite_items = String::new();
+    for entry in favorites {
+        write_entry(&mut favorite_items, entry)?;
+    }
+
+    write!(
+        out,
+        "{}",
+        template
+            .replace(FAVORITES_PLACEHOLDER, &favorite_items)
+            .replace(APPS_PLACEHOLDER, &apps)
+    )
+}
+
+fn write_node<W: Write>(
+    out: &mut W,
+    node: &MenuNode,
+    icons: &CategoryIcons,
+    path: &str,
+) -> fmt::Result {
+    writeln!(
+        out,
+        "<menu id=\"boxmenu-{}\" label=\"{}\"{}>",
+        MenuNode::menu_id(path),
+        escape(node.label()),
+        icon_attr(icons.get(path, node).as_deref())
+    )?;
+    write_items(out, node, icons, path)?;
+    writeln!(out, "</menu>")
+}
+
+fn write_items<W: Write>(
+    out: &mut W,
+    node: &MenuNode,
+    icons: &CategoryIcons,
+    path: &str,
+) -> fmt::Result {
+    for item in node.items(path) {
+        match item {
+            MenuItem::Menu(child_path, child) => write_node(out, child, icons, &child_path)?,
+            MenuItem::Entry(entry) => write_entry(out, entry)?,
+            MenuItem::Separator => writeln!(out, "<separator />")?,
+        }
+    }
+    Ok(())
+}
+
+fn write_entry<W: Write>(out: &mut W, entry: &Entry) -> fmt::Result {
+    writeln!(
+        out,
+        "<item label=\"{}\"{}><action name=\"Execute\"><command>{}</command></action></item>",
+        escape(entry.label.as_str()),
+        icon_attr(entry.icon.as_deref()),
+        entry.exec,
+    )
+}
+
+fn icon_attr(icon: Option<&Path>) -> String {
+    icon.map(|p| format!(" icon=\"{}\"", p.display()))
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::format::test_support::{category_icon_path, excluded, icons, sample_menu};
+    use std::path::PathBuf;
+
+    #[test]
+    fn write_menu_golden() {
+        let (config, root) = sample_menu();
+        let mut out = String::new();
+        write_menu(
+            &mut out,
+            &root,
+            "",
+            &CategoryIcons::new(&config, &icons()),
+            &excluded(),
+        )
+        .unwrap();
+
+        let expected = format!(
+            "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+             <openbox_menu xmlns=\"http://openbox.org/\" xmlns:xsi=\"http://www.w3.org/2001/XMLSchema-instance\" xsi:schemaLocation=\"http://openbox.org/\" >\n\
+             <menu id=\"boxmenu-Apps\" label=\"Apps\">\n\
+             <menu id=\"boxmenu-Apps-Office\" label=\"Office\">\n\
+             <item label=\"Writer\"><action name=\"Execute\"><command>writer</command></action></item>\n\
+             </menu>\n\
+             </menu>\n\
+             <menu id=\"boxmenu-Graphics\" label=\"Graphics\" icon=\"{}\">\n\
+             <item label=\"Draw (beta) &amp; &quot;co&quot;\" icon=\"/icons/draw.png\"><action name=\"Execute\"><command>draw --title {{x}}</command></action></item>\n\
+             <item label=\"Viewer\"><action name=\"Execute\"><command>viewer %f</command></action></item>\n\
+             </menu>\n\
+             </openbox_menu>\n\
+             <!-- Excluded entries (visibility filtering):\n  \
+             Hidden App (NoDisplay=true)\n\
+             -->\n",
+            category_icon_path().display()
+        );
+        assert_eq!(out, expected);
+    }
+
+    #[test]
+    fn write_static_fills_template_placeholders() {
+        let (config, root) = sample_menu();
+        let favorites = vec![Entry {
+            id: "kitty".into(),
+            label: "Terminal".into(),
+            exec: "kitty".into(),
+            icon: Some(PathBuf::from("/icons/kitty.svg")),
+        }];
+        let template = "<openbox_menu>\n<menu id=\"root-menu\" label=\"Openbox 3\">\n{{favorites}}<separator />\n{{apps}}<item label=\"Exit\"><action name=\"Exit\" /></item>\n</menu>\n</openbox_menu>\n";
+
+        let mut out = String::new();
+        write_static(
+            &mut out,
+            &root,
+            &CategoryIcons::new(&config, &icons()),
+            Some(template),
+            &favorites,
+        )
+        .unwrap();
+
+        let expected = format!(
+            "<openbox_menu>\n\
+             <menu id=\"root-menu\" label=\"Openbox 3\">\n\
+             <item label=\"Terminal\" icon=\"/icons/kitty.svg\"><action name=\"Execute\"><command>kitty</command></action></item>\n\
+             <separator />\n\
+             <menu id=\"boxmenu-Apps\" label=\"Apps\">\n\
+             <menu id=\"boxmenu-Apps-Office\" label=\"Office\">\n\
+             <item label=\"Writer\"><action name=\"Execute\"><command>writer</command></action></item>\n\
+             </menu>\n\
+             </menu>\n\
+             <menu id=\"boxmenu-Graphics\" label=\"Graphics\" icon=\"{}\">\n\
+             <item label=\"Draw (beta) &amp; &quot;co&quot;\" icon=\"/icons/draw.png\"><action name=\"Execute\"><command>draw --title {{x}}</command></action></item>\n\
+             <item label=\"Viewer\"><action name=\"Execute\"><command>viewer %f</command></action></item>\n\
+             </menu>\n\
+             <item label=\"Exit\"><action name=\"Exit\" /></item>\n\
+             </menu>\n\
+             </openbox_menu>\n",
+            category_icon_path().display()
+        );
+        assert_eq!(out, expected);
+    }
+
+    #[test]
+    fn write_static_default_template_has_root_menu() {
+        let (config, root) = sample_menu();
+        let mut out = String::new();
+        write_static(
+            &mut out,
+            &root,
+            &CategoryIcons::new(&config, &icons()),
+            None,
+            &[],
+        )
+        .unwrap();
+
+        assert!(out.starts_with("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n"));
+        assert!(out.contains("<menu id=\"root-menu\" label=\"\">\n<menu id=\"boxmenu-Apps\""));
+        assert!(out.ends_with("</menu>\n</menu>\n</openbox_menu>\n"));
+    }
+
+    #[test]
+    fn write_lazy_menu_emits_pipemenu_stubs() {
+        let (config, root) = sample_menu();
+        let command = |path: &str| format!("box-menu-rs --lazy --submenu '{}'", path);
+
+        let mut out = String::new();
+        write_lazy_menu(
+            &mut out,
+            &root,
+            "",
+            &CategoryIcons::new(&config, &icons()),
+            &[],
+            &command,
+        )
+        .unwrap();
+        let expected = format!(
+            "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+             <openbox_menu xmlns=\"http://openbox.org/\" xmlns:xsi=\"http://www.w3.org/2001/XMLSchema-instance\" xsi:schemaLocation=\"http://openbox.org/\" >\n\
+             <menu id=\"boxmenu-Apps\" label=\"Apps\" execute=\"box-menu-rs --lazy --submenu &apos;Apps&apos;\" />\n\
+             <menu id=\"boxmenu-Graphics\" label=\"Graphics\" icon=\"{}\" execute=\"box-menu-rs --lazy --submenu &apos;Graphics&apos;\" />\n\
+             </openbox_menu>\n",
+            category_icon_path().display()
+        );
+        assert_eq!(out, expected);
+
+        let mut out = String::new();
+        let apps = root.subtree("Apps").unwrap();
+        write_lazy_menu(
+            &mut out,
+            apps,
+            "Apps",
+            &CategoryIcons::new(&config, &icons()),
+            &[],
+            &command,
+        )
+        .unwrap();
+        assert!(out.contains(
+            "<menu id=\"boxmenu-Apps-Office\" label=\"Office\" execute=\"box-menu-rs --lazy --submenu &apos;Apps/Office&apos;\" />\n"
+        ));
+    }
+}
@@ -0,0 +1,140 @@
+use super::CategoryIcons;
+use crate::menu::{Entry, MenuItem, MenuNode};
+use std::{
+    fmt::{self, Write},
+    path::Path,
+};
+
+/// Writes a pekwm dynamic menu for `Actions = "Dynamic box-menu-rs --format pekwm"`.
+pub fn write_menu<W: Write>(
+    out: &mut W,
+    root: &MenuNode,
+    path: &str,
+    icons: &CategoryIcons,
+    excluded: &[(String, String)],
+) -> fmt::Result {
+    if !excluded.is_empty() {
+        writeln!(out, "# Excluded entries (visibility filtering):")?;
+        for (label, reason) in excluded {
+            writeln!(out, "#   {} ({})", label, reason)?;
+        }
+    }
+
+    writeln!(out, "Dynamic {{")?;
+    write_items(out, root, icons, path, 1)?;
+    writeln!(out, "}}")
+}
+
+fn write_node<W: Write>(
+    out: &mut W,
+    node: &MenuNode,
+    icons: &CategoryIcons,
+    path: &str,
+    depth: usize,
+) -> fmt::Result {
+    writeln!(
+        out,
+        "{}Submenu = {} {{{}",
+        indent(depth),
+        quote(node.label()),
+        icon_field(icons.get(path, node).as_deref())
+    )?;
+    write_items(out, node, icons, path, depth + 1)?;
+    writeln!(out, "{}}}", indent(depth))
+}
+
+fn write_items<W: Write>(
+    out: &mut W,
+    node: &MenuNode,
+    icons: &CategoryIcons,
+    path: &str,
+    depth: usize,
+) -> fmt::Result {
+    for item in node.items(path) {
+        match item {
+            MenuItem::Menu(child_path, child) => write_node(out, child, icons, &child_path, depth)?,
+            MenuItem::Entry(entry) => write_entry(out, entry, depth)?,
+            MenuItem::Separator => writeln!(out, "{}Separator {{}}", indent(depth))?,
+        }
+    }
+    Ok(())
+}
+
+fn write_entry<W: Write>(out: &mut W, entry: &Entry, depth: usize) -> fmt::Result {
+    writeln!(
+        out,
+        "{}Entry = {} {{{} Actions = {} }}",
+        indent(depth),
+        quote(&entry.label),
+        icon_field(entry.icon.as_deref()),
+        quote(&format!("Exec {} &", entry.exec))
+    )
+}
+
+fn indent(depth: usize) -> String {
+    "  ".repeat(depth)
+}
+
+fn icon_field(icon: Option<&Path>) -> String {
+    icon.map(|p| format!(" Icon = {};", quote(&p.to_string_lossy())))
+        .unwrap_or_default()
+}
+
+/// pekwm strings are double-quoted, with backslash escaping quotes,
+/// backslashes and the `$` that would otherwise start a variable.
+fn quote(raw: &str) -> String {
+    let mut quoted = String::with_capacity(raw.len() + 2);
+    quoted.push('"');
+    for ch in raw.chars() {
+        if matches!(ch, '"' | '\\' | '$') {
+            quoted.push('\\');
+        }
+        quoted.push(ch);
+    }
+    quoted.push('"');
+    quoted
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::format::test_support::{category_icon_path, excluded, icons, sample_menu};
+
+    #[test]
+    fn quote_escapes_specials() {
+        assert_eq!(quote("plain"), "\"plain\"");
+        assert_eq!(quote("say \"$HOME\" \\"), "\"say \\\"\\$HOME\\\" \\\\\"");
+    }
+
+    #[test]
+    fn write_menu_golden() {
+        let (config, root) = sample_menu();
+        let mut out = String::new();
+        write_menu(
+            &mut out,
+            &root,
+            "",
+            &CategoryIcons::new(&config, &icons()),
+            &excluded(),
+        )
+        .unwrap();
+
+        let expected = format!(
+            "# Excluded entries (visibility filtering):\n\
+             #   Hidden App (NoDisplay=true)\n\
+             Dynamic {{\n\
+             \x20 Submenu = \"Apps\" {{\n\
+             \x20   Submenu = \"Office\" {{\n\
+             \x20     Entry = \"Writer\" {{ Actions = \"Exec writer &\" }}\n\
+             \x20   }}\n\
+             \x20 }}\n\
+             \x20 Submenu = \"Graphics\" {{ Icon = \"{}\";\n\
+             \x20   Entry = \"Draw (beta) & \\\"co\\\"\" {{ Icon = \"/icons/draw.png\"; Actions = \"Exec draw --title {{x}} &\" }}\n\
+             \x20   Entry = \"Viewer\" {{ Actions = \"Exec viewer %f &\" }}\n\
+             \x20 }}\n\
+             }}\n",
+            category_icon_path().display()
+        );
+        assert_eq!(out, expected);
+    }
+}
@@ -1,48 +1,801 @@
+use crate::{
+    cache::{self, Fingerprint, IconCache, IconKey},
+    config::{IconFallback, IconFormat, Options},
+    theme::detect_theme,
+    xdg,
+};
+use freedesktop_desktop_entry::DesktopEntry;
 use freedesktop_icons::lookup;
-use std::{path::PathBuf, process::Command, sync::OnceLock};
+use std::{
+    collections::HashSet,
+    fs,
+    io::{self, Read},
+    path::{Path, PathBuf},
+    sync::{Mutex, OnceLock},
+};
 
-static VALUE: OnceLock<String> = OnceLock::new();
+/// Icon themes tried in order for `options`: `icon_themes` or `icon_theme`,
+/// otherwise the detected theme.
+pub fn themes_for(options: &Options) -> Vec<String> {
+    let themes = options.configured_themes();
+    if themes.is_empty() {
+        vec![detect_theme()]
+    } else {
+        themes
+    }
+}
+
+/// Modification times of the icon base directories and of every directory,
+/// size subdirectory and `icon-theme.cache` of `themes`, the themes they
+/// inherit from and `hicolor`. Installing icons with a package manager
+/// updates them.
+fn theme_fingerprint(themes: &[String]) -> Fingerprint {
+    let bases = xdg::icon_dirs();
+    let mut fingerprint = Fingerprint::new();
+    fingerprint.value("theme", &themes.join(":"));
+    for dir in xdg::pixmap_dirs() {
+        fingerprint.file(&dir);
+    }
+    for base in &bases {
+        fingerprint.file(base);
+    }
+    for base in xdg::flatpak_icon_dirs() {
+        fingerprint.file(&base.join("hicolor"));
+        fingerprint.file(&base.join("hicolor/icon-theme.cache"));
+    }
+    for theme in lookup_order(themes, &bases) {
+        for base in &bases {
+            fingerprint_theme_dir(&mut fingerprint, &base.join(&theme));
+        }
+    }
+    fingerprint
+}
 
-pub fn theme() -> &'static String {
-    VALUE.get_or_init(detect_theme)
+/// Records the theme directory `dir`, its `index.theme` and
+/// `icon-theme.cache`, and the subdirectories the index lists. Adding an
+/// icon only changes the subdirectory it is added to.
+fn fingerprint_theme_dir(fingerprint: &mut Fingerprint, dir: &Path) {
+    fingerprint.file(dir);
+    fingerprint.file(&dir.join("icon-theme.cache"));
+    fingerprint.file(&dir.join("index.theme"));
+    for subdir in theme_size_dirs(dir) {
+        fingerprint.file(&subdir);
+    }
 }
 
-pub fn set_theme(theme: String) {
-    let _ = VALUE.set(theme);
+/// The subdirectories of the theme directory `dir` that its `index.theme`
+/// lists, empty if it has none.
+pub fn theme_size_dirs(dir: &Path) -> Vec<PathBuf> {
+    let Ok(index) = fs::read_to_string(dir.join("index.theme")) else {
+        return Vec::new();
+    };
+    theme_subdirs(&index)
+        .into_iter()
+        .map(|subdir| dir.join(subdir))
+        .collect()
 }
 
-fn detect_theme() -> String {
-    if let Ok(output) = Command::new("gsettings")
-        .args(["get", "org.gnome.desktop.interface", "icon-theme"])
-        .output()
-        && output.status.success()
-    {
-        let theme = String::from_utf8_lossy(&output.stdout)
-            .trim()
-            .trim_matches('"')
-            .trim_matches('"')
-            .trim_matches('\'')
-            .to_string();
-        if !theme.is_empty() {
-            return theme;
+/// The subdirectories listed in `Directories=` and `ScaledDirectories=` of
+/// the `index.theme` contents `index`.
+fn theme_subdirs(index: &str) -> Vec<&str> {
+    index
+        .lines()
+        .filter_map(|line| {
+            let (key, value) = line.split_once('=')?;
+            matches!(key.trim(), "Directories" | "ScaledDirectories").then_some(value)
+        })
+        .flat_map(|value| value.split(','))
+        .map(str::trim)
+        .filter(|subdir| !subdir.is_empty())
+        .collect()
+}
+
+/// The chains of [`theme_chain`] for all `themes` in order, each theme
+/// once and `hicolor` last.
+pub fn lookup_order(themes: &[String], bases: &[PathBuf]) -> Vec<String> {
+    let mut order: Vec<String> = Vec::new();
+    for theme in themes {
+        for name in theme_chain(theme, bases) {
+            if name != "hicolor" && !order.contains(&name) {
+                order.push(name);
+            }
+        }
+    }
+    order.push("hicolor".into());
+    order
+}
+
+/// `theme` followed by the themes it inherits from, ending with `hicolor`.
+pub fn theme_chain(theme: &str, bases: &[PathBuf]) -> Vec<String> {
+    let mut chain = vec![theme.to_string()];
+    let mut seen: HashSet<String> = chain.iter().cloned().collect();
+    let mut i = 0;
+    while i < chain.len() {
+        for base in bases {
+            let Ok(index) = fs::read_to_string(base.join(&chain[i]).join("index.theme")) else {
+                continue;
+            };
+            let inherits = index
+                .lines()
+                .find_map(|line| line.trim().strip_prefix("Inherits="))
+                .unwrap_or_default();
+            for parent in inherits.split(',').map(str::trim) {
+                if !parent.is_empty() && seen.insert(parent.to_string()) {
+                    chain.push(parent.to_string());
+                }
+            }
+            break;
+        }
+        i += 1;
+    }
+    if seen.insert("hicolor".into()) {
+        chain.push("hicolor".into());
+    }
+    chain
+}
+
+/// Finds the image files for icon names of desktop entries and menus.
+pub trait IconResolver: Sync {
+    /// Path of the icon `name`, if one is found.
+    fn lookup(&self, name: &str) -> Option<PathBuf>;
+
+    /// Like [`lookup`](Self::lookup), but an existing file path is returned
+    /// as is. Absolute paths are never looked up as names.
+    fn resolve(&self, name_or_path: &str) -> Option<PathBuf> {
+        let path = PathBuf::from(name_or_path);
+        if path.is_file() {
+            return Some(path);
+        }
+        if path.is_absolute() {
+            return None;
+        }
+        self.lookup(name_or_path)
+    }
+
+    /// Icon files found but rejected since the last call, each with the
+    /// reason, for diagnostics. Only [`ValidatedIcons`] and [`ThemeIcons`]
+    /// created [`with_validation`](ThemeIcons::with_validation) reject any.
+    fn take_rejected(&self) -> Vec<(PathBuf, String)> {
+        Vec::new()
+    }
+
+    /// Stores the lookups of this run for the next one, if the resolver
+    /// keeps a disk cache.
+    fn save_cache(&self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl<R: IconResolver + ?Sized> IconResolver for Box<R> {
+    fn lookup(&self, name: &str) -> Option<PathBuf> {
+        (**self).lookup(name)
+    }
+
+    fn resolve(&self, name_or_path: &str) -> Option<PathBuf> {
+        (**self).resolve(name_or_path)
+    }
+
+    fn take_rejected(&self) -> Vec<(PathBuf, String)> {
+        (**self).take_rejected()
+    }
+
+    fn save_cache(&self) -> io::Result<()> {
+        (**self).save_cache()
+    }
+}
+
+/// Looks icons up in freedesktop icon themes, using the disk cache if
+/// created [`with_disk_cache`](Self::with_disk_cache).
+///
+/// With several themes, an icon of an earlier theme or the themes it
+/// inherits from wins; only if none has it, the `hicolor` and `pixmaps`
+/// fallback of the first theme finding one is used.
+pub struct ThemeIcons {
+    themes: Vec<String>,
+    size: u16,
+    scale: u16,
+    format: IconFormat,
+    /// Directories of every theme in `themes` and the themes it inherits
+    /// from, except `hicolor`.
+    theme_dirs: OnceLock<Vec<Vec<PathBuf>>>,
+    /// Icon base, `pixmaps` and Flatpak export directories found icons
+    /// must be below.
+    search_dirs: Vec<PathBuf>,
+    disk_cache: Option<Mutex<IconCache>>,
+    /// Whether lookups are memoized in the process-wide cache of the lookup
+    /// library, which is never invalidated.
+    memory_cache: bool,
+    /// Files skipped by [`with_validation`](Self::with_validation), if set.
+    rejected: Option<Mutex<Vec<(PathBuf, String)>>>,
+}
+
+impl ThemeIcons {
+    /// Looks icons up in `themes` in order, preferring icons of `size`
+    /// pixels at `scale` (2 for HiDPI) in `format`.
+    pub fn new(themes: Vec<String>, size: u16, scale: u16, format: IconFormat) -> Self {
+        Self {
+            themes,
+            size,
+            scale,
+            format,
+            theme_dirs: OnceLock::new(),
+            search_dirs: xdg::icon_dirs()
+                .into_iter()
+                .chain(xdg::pixmap_dirs())
+                .chain(xdg::flatpak_icon_dirs())
+                .collect(),
+            disk_cache: None,
+            memory_cache: true,
+            rejected: None,
+        }
+    }
+
+    /// Uses the themes of [`themes_for`] and `icon_size`, `icon_scale` and
+    /// `icon_format` of `options`.
+    pub fn from_options(options: &Options) -> Self {
+        Self::new(
+            themes_for(options),
+            options.icon_size,
+            options.icon_scale,
+            options.icon_format,
+        )
+    }
+
+    /// Reuses lookups of earlier runs stored in the cache directory until
+    /// the icon themes change; [`IconResolver::save_cache`] stores the ones
+    /// of this run. The disk cache replaces the memory cache.
+    pub fn with_disk_cache(mut self) -> Self {
+        let cache = IconCache::load(&cache::cache_dir(), &theme_fingerprint(&self.themes));
+        self.disk_cache = Some(Mutex::new(cache));
+        self.memory_cache = false;
+        self
+    }
+
+    /// Looks every icon up on disk, for processes that keep running while
+    /// icons are installed or removed.
+    pub fn without_memory_cache(mut self) -> Self {
+        self.memory_cache = false;
+        self
+    }
+
+    /// Skips icon files that are not valid images, see [`check_icon_file`],
+    /// so that a broken icon of one theme falls through to the next theme.
+    pub fn with_validation(mut self) -> Self {
+        self.rejected = Some(Mutex::new(Vec::new()));
+        self
+    }
+
+    /// The icon themes tried in order.
+    pub fn themes(&self) -> &[String] {
+        &self.themes
+    }
+}
+
+impl Default for ThemeIcons {
+    fn default() -> Self {
+        Self::from_options(&Options::default())
+    }
+}
+
+impl ThemeIcons {
+    fn find(&self, name: &str, theme: &str) -> Option<PathBuf> {
+        let mut builder = lookup(name)
+            .with_theme(theme)
+            .with_size(self.size)
+            .with_scale(self.scale);
+        if self.memory_cache {
+            builder = builder.with_cache();
+        }
+        if self.format == IconFormat::Svg {
+            builder = builder.force_svg();
+        }
+        // The lookup library also falls back to paths relative to the
+        // working directory; only icon directories are searched.
+        builder
+            .find()
+            .filter(|path| self.search_dirs.iter().any(|dir| path.starts_with(dir)))
+            .and_then(|path| self.check(path))
+    }
+
+    /// `path` unless validating and it is not a valid image, then the file
+    /// is recorded as rejected.
+    fn check(&self, path: PathBuf) -> Option<PathBuf> {
+        let Some(rejected) = &self.rejected else {
+            return Some(path);
+        };
+        match check_icon_file(&path) {
+            Ok(()) => Some(path),
+            Err(reason) => {
+                record_rejected(&mut rejected.lock().unwrap(), path, reason);
+                None
+            }
         }
     }
-    "hicolor".into()
+
+    fn find_in_themes(&self, name: &str) -> Option<PathBuf> {
+        let themes = &self.themes;
+        if let [theme] = &themes[..] {
+            return self.find(name, theme);
+        }
+        let theme_dirs = self.theme_dirs.get_or_init(|| {
+            let bases = xdg::icon_dirs();
+            themes
+                .iter()
+                .map(|theme| {
+                    theme_chain(theme, &bases)
+                        .iter()
+                        .filter(|name| *name != "hicolor" || *name == theme)
+                        .flat_map(|name| bases.iter().map(move |base| base.join(name)))
+                        .collect()
+                })
+                .collect()
+        });
+
+        let mut fallback = None;
+        for (theme, dirs) in themes.iter().zip(theme_dirs) {
+            let Some(path) = self.find(name, theme) else {
+                continue;
+            };
+            if dirs.iter().any(|dir| path.starts_with(dir)) {
+                return Some(path);
+            }
+            fallback.get_or_insert(path);
+        }
+        fallback
+    }
+
+    /// Icons the themes do not cover: the `hicolor` icons exported by
+    /// Flatpak, then the legacy `pixmaps` directories.
+    fn find_outside_themes(&self, name: &str) -> Option<PathBuf> {
+        if name.contains('/') {
+            return None;
+        }
+        let svg = self.format == IconFormat::Svg;
+        find_exported_icon(
+            name,
+            &xdg::flatpak_icon_dirs(),
+            self.size.saturating_mul(self.scale),
+            svg,
+        )
+        .and_then(|path| self.check(path))
+        .or_else(|| find_pixmap(name, &xdg::pixmap_dirs(), svg).and_then(|path| self.check(path)))
+    }
+}
+
+/// The `hicolor` icon `name` below the first of `bases` having it, in the
+/// size directory closest to `pixels`; scalable icons first if `svg`.
+fn find_exported_icon(name: &str, bases: &[PathBuf], pixels: u16, svg: bool) -> Option<PathBuf> {
+    bases.iter().find_map(|base| {
+        let mut dirs: Vec<PathBuf> = fs::read_dir(base.join("hicolor"))
+            .ok()?
+            .filter_map(Result::ok)
+            .map(|entry| entry.path())
+            .collect();
+        dirs.sort();
+        dirs.iter()
+            .filter_map(|dir| {
+                let distance = size_distance(dir.file_name()?.to_str()?, pixels, svg)?;
+                let path = ["png", "svg", "xpm"]
+                    .iter()
+                    .map(|extension| dir.join("apps").join(format!("{}.{}", name, extension)))
+                    .find(|path| path.is_file())?;
+                Some((distance, path))
+            })
+            .min_by_key(|(distance, _)| *distance)
+            .map(|(_, path)| path)
+    })
+}
+
+/// How far the icon size directory `dir`, e.g. `48x48`, `24x24@2` or
+/// `scalable`, is from `pixels`; `None` for other directories. With `svg`
+/// scalable icons come before any fixed size, otherwise after the exact one.
+fn size_distance(dir: &str, pixels: u16, svg: bool) -> Option<u16> {
+    if dir == "scalable" {
+        return Some(if svg { 0 } else { 1 });
+    }
+    let (size, scale) = dir.split_once('@').unwrap_or((dir, "1"));
+    let (width, _) = size.split_once('x')?;
+    let actual = width
+        .parse::<u16>()
+        .ok()?
+        .saturating_mul(scale.parse::<u16>().ok()?);
+    Some(actual.abs_diff(pixels).saturating_add(u16::from(svg)))
+}
+
+/// `name` in the first of the `pixmaps` directories `dirs` having it, either
+/// with the extension given in `name` or as `.png`, `.svg` or `.xpm`.
+fn find_pixmap(name: &str, dirs: &[PathBuf], svg: bool) -> Option<PathBuf> {
+    let extensions = if svg {
+        ["svg", "png", "xpm"]
+    } else {
+        ["png", "svg", "xpm"]
+    };
+    dirs.iter().find_map(|dir| {
+        Path::new(name)
+            .extension()
+            .map(|_| dir.join(name))
+            .into_iter()
+            .chain(
+                extensions
+                    .iter()
+                    .map(|extension| dir.join(format!("{}.{}", name, extension))),
+            )
+            .find(|path| path.is_file())
+    })
+}
+
+impl IconResolver for ThemeIcons {
+    fn lookup(&self, name: &str) -> Option<PathBuf> {
+        let key = IconKey {
+            name: name.to_string(),
+            theme: self.themes.join(":"),
+            size: self.size,
+            scale: self.scale,
+            svg: self.format == IconFormat::Svg,
+            validated: self.rejected.is_some(),
+        };
+        if let Some(cache) = &self.disk_cache
+            && let Some(result) = cache.lock().unwrap().get(&key)
+        {
+            return result;
+        }
+
+        let result = self
+            .find_in_themes(name)
+            .or_else(|| self.find_outside_themes(name));
+        if let Some(cache) = &self.disk_cache {
+            cache.lock().unwrap().insert(key, result.clone());
+        }
+        result
+    }
+
+    fn take_rejected(&self) -> Vec<(PathBuf, String)> {
+        self.rejected
+            .as_ref()
+            .map(|rejected| std::mem::take(&mut *rejected.lock().unwrap()))
+            .unwrap_or_default()
+    }
+
+    fn save_cache(&self) -> io::Result<()> {
+        match &self.disk_cache {
+            Some(cache) => cache.lock().unwrap().save(),
+            None => Ok(()),
+        }
+    }
+}
+
+/// How [`ThemeIcons`] keep the results of icon lookups.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum IconCaching {
+    /// Every lookup goes to disk.
+    None,
+    /// Lookups are kept for the rest of the process.
+    Memory,
+    /// Lookups are kept in the cache directory until the themes change.
+    Disk,
+}
+
+/// The resolver described by `options`: [`ThemeIcons`] with `caching`,
+/// skipping broken files with `validate_icons` and converting SVG icons to
+/// PNG with `rasterize_svg` if built with the `rasterize` feature.
+pub fn configured_icons(options: &Options, caching: IconCaching) -> Box<dyn IconResolver> {
+    let mut theme_icons = ThemeIcons::from_options(options);
+    theme_icons = match caching {
+        IconCaching::None => theme_icons.without_memory_cache(),
+        IconCaching::Memory => theme_icons,
+        IconCaching::Disk => theme_icons.with_disk_cache(),
+    };
+    if options.validate_icons {
+        theme_icons = theme_icons.with_validation();
+    }
+    let mut icons: Box<dyn IconResolver> = Box::new(theme_icons);
+    if options.validate_icons {
+        icons = Box::new(ValidatedIcons::new(icons));
+    }
+    if options.rasterize_svg {
+        #[cfg(feature = "rasterize")]
+        {
+            icons = Box::new(crate::rasterize::RasterizedIcons::from_options(
+                icons, options,
+            ));
+        }
+        #[cfg(not(feature = "rasterize"))]
+        eprintln!("warning: rasterize_svg requires box-menu-rs built with the rasterize feature");
+    }
+    icons
+}
+
+/// Wraps another resolver and drops the files it finds that are not
+/// readable PNG, SVG or XPM images, so that the next step of the icon
+/// fallback chain is tried instead of emitting a broken icon. Unlike
+/// [`ThemeIcons::with_validation`], this also covers configured file paths.
+pub struct ValidatedIcons<R> {
+    inner: R,
+    rejected: Mutex<Vec<(PathBuf, String)>>,
+}
+
+impl<R: IconResolver> ValidatedIcons<R> {
+    pub fn new(inner: R) -> Self {
+        Self {
+            inner,
+            rejected: Mutex::new(Vec::new()),
+        }
+    }
+
+    fn check(&self, path: PathBuf) -> Option<PathBuf> {
+        match check_icon_file(&path) {
+            Ok(()) => Some(path),
+            Err(reason) => {
+                record_rejected(&mut self.rejected.lock().unwrap(), path, reason);
+                None
+            }
+        }
+    }
+}
+
+/// Adds `path` to `rejected` unless it is already listed.
+fn record_rejected(rejected: &mut Vec<(PathBuf, String)>, path: PathBuf, reason: String) {
+    if !rejected.iter().any(|(known, _)| *known == path) {
+        rejected.push((path, reason));
+    }
+}
+
+impl<R: IconResolver> IconResolver for ValidatedIcons<R> {
+    fn lookup(&self, name: &str) -> Option<PathBuf> {
+        self.inner.lookup(name).and_then(|path| self.check(path))
+    }
+
+    fn resolve(&self, name_or_path: &str) -> Option<PathBuf> {
+        self.inner
+            .resolve(name_or_path)
+            .and_then(|path| self.check(path))
+    }
+
+    fn take_rejected(&self) -> Vec<(PathBuf, String)> {
+        let mut rejected = self.inner.take_rejected();
+        for (path, reason) in std::mem::take(&mut *self.rejected.lock().unwrap()) {
+            record_rejected(&mut rejected, path, reason);
+        }
+        rejected
+    }
+
+    fn save_cache(&self) -> io::Result<()> {
+        self.inner.save_cache()
+    }
+}
+
+/// Checks by its first bytes that `path` is a readable PNG, SVG (or gzip
+/// compressed `.svgz`) or XPM image; the error describes the problem.
+pub fn check_icon_file(path: &Path) -> Result<(), String> {
+    let mut head = Vec::new();
+    fs::File::open(path)
+        .and_then(|file| file.take(65536).read_to_end(&mut head))
+        .map_err(|err| format!("not readable: {}", err))?;
+    if head.is_empty() {
+        return Err("empty file".into());
+    }
+    let svgz = path
+        .extension()
+        .is_some_and(|extension| extension.eq_ignore_ascii_case("svgz"));
+    let valid = head.starts_with(b"\x89PNG\r\n\x1a\n")
+        || head.starts_with(b"/* XPM */")
+        || (svgz && head.starts_with(b"\x1f\x8b"))
+        || has_svg_root(&String::from_utf8_lossy(&head));
+    if valid {
+        Ok(())
+    } else {
+        Err("not a PNG, SVG or XPM image".into())
+    }
+}
+
+/// Whether the first element of the XML document `text` is `<svg>`, after
+/// an optional XML declaration, comments, processing instructions and a
+/// doctype.
+fn has_svg_root(text: &str) -> bool {
+    let mut rest = text.trim_start_matches('\u{feff}');
+    loop {
+        rest = rest.trim_start();
+        let end = if rest.starts_with("<?") {
+            rest.find("?>").map(|end| end + 2)
+        } else if rest.starts_with("<!--") {
+            rest.find("-->").map(|end| end + 3)
+        } else if rest.starts_with("<!DOCTYPE") {
+            // The internal subset in `[...]` may contain `>` itself.
+            let subset_end = match (rest.find('['), rest.find('>')) {
+                (Some(open), Some(close)) if open < close => {
+                    rest[open..].find(']').map_or(open, |close| open + close)
+                }
+                _ => 0,
+            };
+            rest[subset_end..].find('>').map(|end| subset_end + end + 1)
+        } else {
+            break;
+        };
+        let Some(end) = end else {
+            return false;
+        };
+        rest = &rest[end..];
+    }
+    rest.strip_prefix("<svg")
+        .and_then(|after| after.chars().next())
+        .is_some_and(|next| next.is_whitespace() || matches!(next, '>' | '/' | ':'))
 }
 
-pub fn lookup_icon(name: &str) -> Option<PathBuf> {
-    lookup(name)
-        .with_theme(theme().as_str())
-        .with_cache()
-        .find()
+/// Looks icons up as `<name>.svg`, `<name>.png` or `<name>.xpm` in a single
+/// directory, e.g. icons shipped with an application.
+pub struct DirectoryIcons {
+    dir: PathBuf,
 }
 
-pub fn resolve_icon(name_or_path: &str) -> Option<PathBuf> {
-    let path = PathBuf::from(name_or_path);
-    if path.is_file() {
-        return Some(path);
+impl DirectoryIcons {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self { dir: dir.into() }
     }
-    lookup_icon(name_or_path)
+}
+
+impl IconResolver for DirectoryIcons {
+    fn lookup(&self, name: &str) -> Option<PathBuf> {
+        ["svg", "png", "xpm"]
+            .iter()
+            .map(|extension| self.dir.join(format!("{}.{}", name, extension)))
+            .find(|path| path.is_file())
+    }
+}
+
+/// Resolves no icons at all, menus are written without them.
+pub struct NoIcons;
+
+impl IconResolver for NoIcons {
+    fn lookup(&self, _name: &str) -> Option<PathBuf> {
+        None
+    }
+
+    fn resolve(&self, _name_or_path: &str) -> Option<PathBuf> {
+        None
+    }
+}
+
+/// Icon names tried for `entry` in the order of `fallback`, each with the
+/// step it came from; `None` where the entry has nothing for a step.
+/// `category_icon` is the icon of the category the entry is shown in.
+pub fn entry_icon_candidates<'a>(
+    entry: &DesktopEntry,
+    fallback: &'a [IconFallback],
+    category_icon: Option<&str>,
+) -> Vec<(&'a IconFallback, Option<String>)> {
+    fallback
+        .iter()
+        .map(|step| {
+            let name = match step {
+                IconFallback::Icon => entry.icon().map(str::to_string),
+                IconFallback::StartupWmClass => entry.startup_wm_class().map(str::to_string),
+                IconFallback::DesktopId => Some(entry.id().to_string()),
+                IconFallback::Exec => entry.exec().and_then(exec_name),
+                IconFallback::Category => category_icon.map(str::to_string),
+                IconFallback::Named(name) => Some(name.clone()),
+            };
+            (step, name.filter(|name| !name.is_empty()))
+        })
+        .collect()
+}
+
+/// Looks up the candidate `name` produced by `step`. `Icon=`, category
+/// icons and fixed names may also be file paths, e.g. the absolute paths
+/// of Snap packages.
+pub fn resolve_candidate(
+    icons: &dyn IconResolver,
+    step: &IconFallback,
+    name: &str,
+) -> Option<PathBuf> {
+    match step {
+        IconFallback::Icon | IconFallback::Category | IconFallback::Named(_) => icons.resolve(name),
+        _ => icons.lookup(name),
+    }
+}
+
+/// The first icon found along `fallback` for `entry`.
+pub fn resolve_entry_icon(
+    entry: &DesktopEntry,
+    fallback: &[IconFallback],
+    category_icon: Option<&str>,
+    icons: &dyn IconResolver,
+) -> Option<PathBuf> {
+    entry_icon_candidates(entry, fallback, category_icon)
+        .into_iter()
+        .find_map(|(step, name)| resolve_candidate(icons, step, &name?))
+}
+
+/// Splits `fallback` before its `category` step: an icon found along the
+/// first part is the same in every output category of an entry.
+pub fn split_at_category(fallback: &[IconFallback]) -> (&[IconFallback], &[IconFallback]) {
+    let position = fallback
+        .iter()
+        .position(|step| *step == IconFallback::Category)
+        .unwrap_or(fallback.len());
+    fallback.split_at(position)
+}
+
+/// The icon of a category named `icon_name`, or else the first fixed name of
+/// `fallback` that is found.
+pub fn resolve_category_icon(
+    icon_name: &str,
+    fallback: &[IconFallback],
+    icons: &dyn IconResolver,
+) -> Option<PathBuf> {
+    find_category_icon(icon_name, fallback, icons).map(|(path, _)| path)
+}
+
+/// Like [`resolve_category_icon`], together with the fixed name of
+/// `fallback` the icon was found by, if any.
+pub fn find_category_icon<'a>(
+    icon_name: &str,
+    fallback: &'a [IconFallback],
+    icons: &dyn IconResolver,
+) -> Option<(PathBuf, Option<&'a str>)> {
+    if let Some(path) = icons.resolve(icon_name) {
+        return Some((path, None));
+    }
+    fallback.iter().find_map(|step| match step {
+        IconFallback::Named(name) => Some((icons.resolve(name)?, Some(name.as_str()))),
+        _ => None,
+    })
+}
+
+/// Size directory and format of an icon file, e.g. `48x48 png` or
+/// `scalable svg`, for diagnostics.
+pub fn icon_details(path: &Path) -> String {
+    let size = path
+        .ancestors()
+        .skip(1)
+        .filter_map(|dir| dir.file_name()?.to_str())
+        .find(|dir| {
+            *dir == "scalable"
+                || dir
+                    .split_once('x')
+                    .is_some_and(|(width, _)| width.parse::<u16>().is_ok())
+        })
+        .unwrap_or("unsized");
+    let format = path
+        .extension()
+        .map(|extension| extension.to_string_lossy().to_lowercase())
+        .unwrap_or_else(|| "unknown".into());
+    format!("{} {}", size, format)
+}
+
+/// Where the icon file `path` comes from, for diagnostics: the theme below
+/// an icon base directory, `flatpak`, `pixmaps` or else `file`.
+pub fn icon_origin(path: &Path) -> String {
+    for base in xdg::icon_dirs() {
+        let Ok(rest) = path.strip_prefix(&base) else {
+            continue;
+        };
+        let mut components = rest.components();
+        if let (Some(theme), Some(_)) = (components.next(), components.next()) {
+            return theme.as_os_str().to_string_lossy().into_owned();
+        }
+    }
+    if xdg::flatpak_icon_dirs()
+        .iter()
+        .any(|dir| path.starts_with(dir))
+    {
+        "flatpak".into()
+    } else if xdg::pixmap_dirs().iter().any(|dir| path.starts_with(dir)) {
+        "pixmaps".into()
+    } else {
+        "file".into()
+    }
+}
+
+/// File name of the program started by the `Exec` line `exec`, skipping a
+/// leading `env` with its variable assignments.
+fn exec_name(exec: &str) -> Option<String> {
+    let program = exec
+        .split_whitespace()
+        .map(|token| token.trim_matches(|c| c == '"' || c == '\''))
+        .find(|token| *token != "env" && !token.contains('='))?;
+    Path::new(program)
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
 }
 
 #[cfg(test)]
@@ -61,7 +814,8 @@ mod tests {
         let file_path = temp_dir.join(format!("box-menu-rs-test-icon-{}.png", timestamp));
 
         fs::write(&file_path, b"test").expect("failed to write temp file");
-        let resolved = resolve_icon(file_path.to_str().expect("invalid temp path"));
+        let resolved =
+            ThemeIcons::default().resolve(file_path.to_str().expect("invalid temp path"));
         assert_eq!(resolved.as_deref(), Some(file_path.as_path()));
 
         fs::remove_file(&file_path).expect("failed to remove temp file");
@@ -78,7 +832,337 @@ mod tests {
             timestamp
         );
 
-        let resolved = resolve_icon(&path_str);
+        let resolved = ThemeIcons::default().resolve(&path_str);
         assert!(resolved.is_none());
     }
+
+    #[test]
+    fn directory_icons_prefer_svg_and_no_icons_resolve_nothing() {
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .expect("time went backwards")
+            .as_nanos();
+        let dir = std::env::temp_dir().join(format!("box-menu-rs-test-icon-dir-{}", timestamp));
+        fs::create_dir_all(&dir).expect("failed to create temp dir");
+        fs::write(dir.join("editor.png"), b"png").expect("failed to write icon");
+        fs::write(dir.join("editor.svg"), b"svg").expect("failed to write icon");
+
+        let icons = DirectoryIcons::new(&dir);
+        assert_eq!(icons.lookup("editor"), Some(dir.join("editor.svg")));
+        assert_eq!(icons.lookup("player"), None);
+        let png = dir.join("editor.png");
+        assert_eq!(icons.resolve(png.to_str().unwrap()), Some(png.clone()));
+        assert_eq!(NoIcons.resolve(png.to_str().unwrap()), None);
+
+        fs::remove_dir_all(&dir).expect("failed to remove temp dir");
+    }
+
+    #[test]
+    fn entry_icon_falls_back_along_chain() {
+        let entry = DesktopEntry::from_str(
+            "/fixtures/org.example.Editor.desktop",
+            "[Desktop Entry]\nType=Application\nName=Editor\nIcon=missing\nStartupWMClass=editor-wm\nExec=env LANG=C /usr/bin/edit-it %F\n",
+            None::<&[&str]>,
+        )
+        .expect("failed to parse entry");
+        let fallback = [
+            IconFallback::Icon,
+            IconFallback::StartupWmClass,
+            IconFallback::DesktopId,
+            IconFallback::Exec,
+            IconFallback::Category,
+            IconFallback::Named("application-x-executable".into()),
+        ];
+        let names: Vec<_> = entry_icon_candidates(&entry, &fallback, None)
+            .into_iter()
+            .map(|(_, name)| name)
+            .collect();
+        assert_eq!(
+            names,
+            [
+                Some("missing".to_string()),
+                Some("editor-wm".to_string()),
+                Some("org.example.Editor".to_string()),
+                Some("edit-it".to_string()),
+                None,
+                Some("application-x-executable".to_string()),
+            ]
+        );
+
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .expect("time went backwards")
+            .as_nanos();
+        let dir = std::env::temp_dir().join(format!("box-menu-rs-test-fallback-{}", timestamp));
+        fs::create_dir_all(&dir).expect("failed to create temp dir");
+        fs::write(dir.join("edit-it.png"), b"png").expect("failed to write icon");
+        fs::write(dir.join("application-x-executable.png"), b"png").expect("failed to write icon");
+        let icons = DirectoryIcons::new(&dir);
+
+        assert_eq!(
+            resolve_entry_icon(&entry, &fallback, None, &icons),
+            Some(dir.join("edit-it.png"))
+        );
+        assert_eq!(
+            resolve_entry_icon(&entry, &fallback[..3], None, &icons),
+            None
+        );
+        assert_eq!(
+            resolve_category_icon("applications-missing", &fallback, &icons),
+            Some(dir.join("application-x-executable.png"))
+        );
+
+        fs::remove_dir_all(&dir).expect("failed to remove temp dir");
+    }
+
+    #[test]
+    fn icon_details_reports_size_directory_and_format() {
+        assert_eq!(
+            icon_details(Path::new(
+                "/usr/share/icons/hicolor/48x48@2/apps/firefox.png"
+            )),
+            "48x48@2 png"
+        );
+        assert_eq!(
+            icon_details(Path::new("/usr/share/icons/Papirus/scalable/apps/gimp.SVG")),
+            "scalable svg"
+        );
+        assert_eq!(
+            icon_details(Path::new("/usr/share/pixmaps/xterm.xpm")),
+            "unsized xpm"
+        );
+    }
+
+    #[test]
+    fn flatpak_exports_and_pixmaps_are_searched() {
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .expect("time went backwards")
+            .as_nanos();
+        let base = std::env::temp_dir().join(format!("box-menu-rs-test-flatpak-{}", timestamp));
+        for (dir, file) in [
+            ("hicolor/16x16/apps", "org.example.App.png"),
+            ("hicolor/48x48/apps", "org.example.App.png"),
+            ("hicolor/24x24@2/apps", "org.example.App.png"),
+            ("hicolor/scalable/apps", "org.example.App.svg"),
+            ("pixmaps", "legacy.xpm"),
+            ("pixmaps", "old.png"),
+        ] {
+            fs::create_dir_all(base.join(dir)).expect("failed to create temp dir");
+            fs::write(base.join(dir).join(file), b"icon").expect("failed to write icon");
+        }
+        let bases = [base.join("missing"), base.clone()];
+        let apps =
+            |dir: &str, file: &str| Some(base.join("hicolor").join(dir).join("apps").join(file));
+
+        assert_eq!(
+            find_exported_icon("org.example.App", &bases, 48, false),
+            apps("24x24@2", "org.example.App.png")
+        );
+        assert_eq!(
+            find_exported_icon("org.example.App", &bases, 16, false),
+            apps("16x16", "org.example.App.png")
+        );
+        assert_eq!(
+            find_exported_icon("org.example.App", &bases, 48, true),
+            apps("scalable", "org.example.App.svg")
+        );
+        assert_eq!(find_exported_icon("missing", &bases, 48, false), None);
+
+        let pixmaps = [base.join("pixmaps")];
+        assert_eq!(
+            find_pixmap("legacy", &pixmaps, false),
+            Some(base.join("pixmaps/legacy.xpm"))
+        );
+        assert_eq!(
+            find_pixmap("old.png", &pixmaps, false),
+            Some(base.join("pixmaps/old.png"))
+        );
+        assert_eq!(find_pixmap("missing", &pixmaps, false), None);
+
+        fs::remove_dir_all(&base).expect("failed to remove temp dir");
+    }
+
+    #[test]
+    fn absolute_icon_paths_are_used_as_is() {
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .expect("time went backwards")
+            .as_nanos();
+        let dir = std::env::temp_dir().join(format!("box-menu-rs-test-snap-{}", timestamp));
+        fs::create_dir_all(dir.join("meta/gui")).expect("failed to create temp dir");
+        let icon = dir.join("meta/gui/icon.png");
+        fs::write(&icon, b"png").expect("failed to write icon");
+        fs::write(dir.join("fallback.png"), b"png").expect("failed to write icon");
+        let parse = |icon: &Path| {
+            let contents = format!(
+                "[Desktop Entry]\nType=Application\nName=Snap\nExec=snapped\nIcon={}\n",
+                icon.display()
+            );
+            DesktopEntry::from_str(
+                Path::new("/fixtures/snap.desktop"),
+                &contents,
+                None::<&[&str]>,
+            )
+            .expect("failed to parse entry")
+        };
+        let fallback = [IconFallback::Icon, IconFallback::Named("fallback".into())];
+        let icons = DirectoryIcons::new(&dir);
+
+        assert_eq!(
+            resolve_entry_icon(&parse(&icon), &fallback, None, &icons),
+            Some(icon.clone())
+        );
+        assert_eq!(
+            resolve_entry_icon(&parse(&dir.join("fallback")), &fallback, None, &icons),
+            Some(dir.join("fallback.png")),
+            "a missing absolute path must not be looked up by name"
+        );
+
+        fs::remove_dir_all(&dir).expect("failed to remove temp dir");
+    }
+
+    #[test]
+    fn validated_icons_skip_broken_files() {
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .expect("time went backwards")
+            .as_nanos();
+        let dir = std::env::temp_dir().join(format!("box-menu-rs-test-validate-{}", timestamp));
+        fs::create_dir_all(&dir).expect("failed to create temp dir");
+        for (file, contents) in [
+            ("good.png", &b"\x89PNG\r\n\x1a\n...."[..]),
+            (
+                "good.svg",
+                b"<?xml version=\"1.0\"?>\n<svg xmlns=\"http://www.w3.org/2000/svg\"/>",
+            ),
+            (
+                "prolog.svg",
+                b"\xef\xbb\xbf<?xml version=\"1.0\"?>\n<!-- Icon -->\n<!DOCTYPE svg [\n<!ENTITY a \"b\">\n]>\n<svg>",
+            ),
+            ("good.xpm", b"/* XPM */\nstatic char *icon[] = {};"),
+            (
+                "page.svg",
+                b"<!DOCTYPE html>\n<html><body><svg></svg></body></html>",
+            ),
+            ("empty.png", b""),
+            ("text.png", b"test"),
+            ("fallback.png", b"\x89PNG\r\n\x1a\n...."),
+        ] {
+            fs::write(dir.join(file), contents).expect("failed to write icon");
+        }
+
+        for good in ["good.png", "good.svg", "prolog.svg", "good.xpm"] {
+            assert_eq!(check_icon_file(&dir.join(good)), Ok(()), "{}", good);
+        }
+        assert_eq!(
+            check_icon_file(&dir.join("empty.png")),
+            Err("empty file".into())
+        );
+        assert!(check_icon_file(&dir.join("text.png")).is_err());
+        assert!(check_icon_file(&dir.join("page.svg")).is_err());
+        assert!(check_icon_file(&dir.join("missing.png")).is_err());
+
+        let icons = ValidatedIcons::new(DirectoryIcons::new(&dir));
+        assert_eq!(icons.lookup("good"), Some(dir.join("good.svg")));
+        assert_eq!(icons.lookup("text"), None);
+        assert_eq!(icons.lookup("text"), None);
+        let rejected = icons.take_rejected();
+        assert_eq!(rejected.len(), 1);
+        assert_eq!(rejected[0].0, dir.join("text.png"));
+        assert!(icons.take_rejected().is_empty());
+
+        let fallback = [IconFallback::Icon, IconFallback::Named("fallback".into())];
+        let entry = DesktopEntry::from_str(
+            Path::new("/fixtures/broken.desktop"),
+            "[Desktop Entry]\nType=Application\nName=Broken\nExec=broken\nIcon=empty\n",
+            None::<&[&str]>,
+        )
+        .expect("failed to parse entry");
+        assert_eq!(
+            resolve_entry_icon(&entry, &fallback, None, &icons),
+            Some(dir.join("fallback.png"))
+        );
+
+        fs::remove_dir_all(&dir).expect("failed to remove temp dir");
+    }
+
+    #[test]
+    fn theme_fingerprint_covers_listed_size_directories() {
+        assert_eq!(
+            theme_subdirs(
+                "[Icon Theme]\nName=Test\nDirectories=16x16/apps, scalable/apps,\nScaledDirectories=16x16@2/apps\n\n[16x16/apps]\nSize=16\n"
+            ),
+            ["16x16/apps", "scalable/apps", "16x16@2/apps"]
+        );
+
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .expect("time went backwards")
+            .as_nanos();
+        let dir = std::env::temp_dir().join(format!("box-menu-rs-test-theme-dirs-{}", timestamp));
+        let theme = dir.join("icons/Test");
+        fs::create_dir_all(theme.join("48x48/apps")).expect("failed to create theme dir");
+        fs::write(
+            theme.join("index.theme"),
+            "[Icon Theme]\nName=Test\nDirectories=48x48/apps\n",
+        )
+        .expect("failed to write index.theme");
+        let fingerprint = || {
+            let mut fingerprint = Fingerprint::new();
+            fingerprint_theme_dir(&mut fingerprint, &theme);
+            fingerprint
+        };
+        let key = IconKey {
+            name: "editor".into(),
+            theme: "Test".into(),
+            size: 48,
+            scale: 1,
+            svg: false,
+            validated: false,
+        };
+        let mut cache = IconCache::load(&dir, &fingerprint());
+        cache.insert(key.clone(), None);
+        cache.save().expect("failed to save icon cache");
+        assert_eq!(IconCache::load(&dir, &fingerprint()).get(&key), Some(None));
+
+        fs::write(theme.join("48x48/apps/editor.png"), b"png").expect("failed to write icon");
+        assert_eq!(IconCache::load(&dir, &fingerprint()).get(&key), None);
+
+        fs::remove_dir_all(&dir).expect("failed to remove temp dir");
+    }
+
+    #[test]
+    fn theme_chain_follows_inherits_and_ends_with_hicolor() {
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .expect("time went backwards")
+            .as_nanos();
+        let base = std::env::temp_dir().join(format!("box-menu-rs-test-themes-{}", timestamp));
+        for (theme, inherits) in [
+            ("Papirus-Dark", "Papirus,breeze"),
+            ("Papirus", "breeze,hicolor"),
+        ] {
+            fs::create_dir_all(base.join(theme)).expect("failed to create theme dir");
+            fs::write(
+                base.join(theme).join("index.theme"),
+                format!("[Icon Theme]\nName={}\nInherits={}\n", theme, inherits),
+            )
+            .expect("failed to write index.theme");
+        }
+
+        assert_eq!(
+            theme_chain("Papirus-Dark", std::slice::from_ref(&base)),
+            ["Papirus-Dark", "Papirus", "breeze", "hicolor"]
+        );
+        assert_eq!(
+            lookup_order(
+                &["Papirus".into(), "Adwaita".into(), "Papirus-Dark".into()],
+                std::slice::from_ref(&base)
+            ),
+            ["Papirus", "breeze", "Adwaita", "Papirus-Dark", "hicolor"]
+        );
+
+        fs::remove_dir_all(&base).expect("failed to remove temp dir");
+    }
 }
@@ -0,0 +1,26 @@
+//! Generates application menus for Openbox and other window managers from
+//! the freedesktop desktop entries.
+//!
+//! [`builder::MenuBuilder`] turns desktop entries into a [`menu::MenuNode`]
+//! tree according to a [`config::Config`], which the writers in [`format`]
+//! render for the supported window managers.
+
+pub mod atomic;
+pub mod builder;
+pub mod cache;
+pub mod config;
+mod escape;
+pub mod export;
+pub mod format;
+pub mod icon;
+pub mod list;
+pub mod menu;
+mod parallel;
+#[cfg(feature = "rasterize")]
+pub mod rasterize;
+pub mod source;
+pub mod theme;
+pub mod timings;
+pub mod visibility;
+pub mod xdg;
+pub mod xdg_menu;
@@ -1,34 +1,339 @@
 use crate::{
-    config::Config,
-    icon::{lookup_icon, resolve_icon},
+    builder::{MenuBuilder, output_categories},
+    config::{Config, IconFallback, IconFormat},
+    icon::{
+        IconResolver, ThemeIcons, entry_icon_candidates, find_category_icon, icon_details,
+        icon_origin, lookup_order, resolve_candidate, resolve_category_icon, resolve_entry_icon,
+        split_at_category,
+    },
+    menu::MenuNode,
+    theme::{DEFAULT_THEME, theme_sources},
     visibility::visibility_exclusion_reason,
+    xdg,
 };
+use clap::ValueEnum;
 use freedesktop_desktop_entry::DesktopEntry;
-use std::collections::HashSet;
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    error::Error,
+    path::PathBuf,
+};
+
+/// Selection of desktop entries reported by [`list_programs`].
+#[derive(Copy, Clone, Debug, ValueEnum)]
+#[value(rename_all = "kebab-case")]
+pub enum ListAction {
+    #[value(help = "All discovered desktop entries.")]
+    All,
+    #[value(help = "Entries with missing entry icon lookup.")]
+    MissingIcons,
+    #[value(help = "Hidden entries excluded by visibility filtering.")]
+    Excluded,
+    #[value(help = "Inspect a specific entry by Name.")]
+    Program,
+    #[value(help = "Icon theme in use and where it was configured.")]
+    Theme,
+    #[value(help = "Where the icon of every category and menu entry was found.")]
+    Icons,
+}
+
+/// Prints the icon themes in use, every source they may be configured in and
+/// the themes icons are looked up in.
+pub fn list_theme(config: &Config) {
+    let sources = theme_sources();
+    let themes = ThemeIcons::from_options(&config.options).themes().to_vec();
+    let origin = if !config.options.icon_themes.is_empty() {
+        "options.icon_themes".to_string()
+    } else if config.options.icon_theme.is_some() {
+        "options.icon_theme".to_string()
+    } else {
+        sources
+            .iter()
+            .find(|source| source.theme.is_some())
+            .map_or_else(|| "default".to_string(), |source| source.origin.clone())
+    };
+    let label = if themes.len() > 1 {
+        "Icon themes"
+    } else {
+        "Icon theme"
+    };
+    println!("{}: {} (from {})", label, themes.join(", "), origin);
+    println!("\nSources in order of precedence:");
+    println!(
+        "  options.icon_themes: {}",
+        if config.options.icon_themes.is_empty() {
+            "<not set>".to_string()
+        } else {
+            config.options.icon_themes.join(", ")
+        }
+    );
+    println!(
+        "  options.icon_theme: {}",
+        config.options.icon_theme.as_deref().unwrap_or("<not set>")
+    );
+    for source in &sources {
+        println!(
+            "  {}: {}",
+            source.origin,
+            source.theme.as_deref().unwrap_or("<not set>")
+        );
+    }
+    println!("  default: {}", DEFAULT_THEME);
+    println!(
+        "\nLookup order: {}",
+        lookup_order(&themes, &xdg::icon_dirs()).join(", ")
+    );
+}
+
+/// Where one category or entry icon was found, for the summary of
+/// [`list_icons`].
+struct IconRecord {
+    entry: bool,
+    origin: Option<String>,
+    fallback: bool,
+    invalid: usize,
+}
+
+/// Prints for every category and menu entry of the menu tree the icon
+/// asked for, where it was found and whether a fallback was needed, then
+/// how many icons each theme provided.
+pub fn list_icons(
+    entries: &[DesktopEntry],
+    locales: &[String],
+    icons: &dyn IconResolver,
+    config: &Config,
+    current_desktop: Option<&HashSet<String>>,
+) -> Result<(), Box<dyn Error>> {
+    let menu = MenuBuilder::new(config)
+        .locales(locales)
+        .icons(icons)
+        .current_desktop(current_desktop)
+        .build(entries)?;
+    let mut menus = Vec::new();
+    collect_menus(&menu.root, "", &mut menus);
+    menus.sort_by(|(a, _), (b, _)| a.cmp(b));
+    let mut records = Vec::new();
+    icons.take_rejected();
+
+    println!("Category icons:");
+    if config.options.icons.categories() {
+        for (path, node) in &menus {
+            let requested = config.icon_for_menu(path, node);
+            let found = find_category_icon(&requested, &config.options.icon_fallback, icons).map(
+                |(icon, fallback)| {
+                    let fallback = fallback.map(|name| ("name".to_string(), name.to_string()));
+                    (fallback, icon)
+                },
+            );
+            let rejected = icons.take_rejected();
+            records.push(print_icon_record(
+                path,
+                Some(&requested),
+                found,
+                &rejected,
+                false,
+            ));
+        }
+    } else {
+        println!("  <disabled by options.icons>");
+    }
+
+    println!("\nEntry icons:");
+    if config.options.icons.entries() {
+        // Map the menu entries back to their desktop entries by desktop file
+        // id to explain how their icons were found, with the icons of the
+        // menus they are shown in for the `category` step.
+        let root = (String::new(), &menu.root);
+        let mut shown_in: HashMap<&str, Vec<String>> = HashMap::new();
+        let mut menu_icons: HashMap<&str, String> = HashMap::new();
+        for (path, node) in std::iter::once(&root).chain(&menus) {
+            menu_icons.insert(path, config.icon_for_menu(path, node));
+            for entry in node.entries() {
+                shown_in.entry(&entry.id).or_default().push(path.clone());
+            }
+        }
+        let mut listed = Vec::new();
+        for entry in entries {
+            if let Some(paths) = shown_in.remove(entry.id()) {
+                let label = entry.full_name(locales).unwrap_or_default().into_owned();
+                listed.push((label, entry, paths));
+            }
+        }
+        listed.sort_by(|(a, ..), (b, ..)| a.cmp(b));
+        for (label, entry, paths) in listed {
+            let categories = icon_categories(entry, config, icons, paths);
+            icons.take_rejected();
+            for category in &categories {
+                let label = match category {
+                    Some(category) if categories.len() > 1 => format!("{} ({})", label, category),
+                    _ => label.clone(),
+                };
+                let mut requested = entry.icon().map(str::to_string);
+                let category_icon = category
+                    .as_deref()
+                    .and_then(|path| menu_icons.get(path))
+                    .map(String::as_str);
+                let found =
+                    entry_icon(entry, config, icons, category_icon).map(|(choice, name, path)| {
+                        let fallback = match choice {
+                            IconChoice::Icon => None,
+                            IconChoice::Override => {
+                                requested = Some(name);
+                                None
+                            }
+                            IconChoice::Fallback(IconFallback::Named(_)) => {
+                                Some(("name".into(), name))
+                            }
+                            IconChoice::Fallback(step) => Some((String::from(step), name)),
+                        };
+                        (fallback, path)
+                    });
+                let rejected = icons.take_rejected();
+                records.push(print_icon_record(
+                    &label,
+                    requested.as_deref(),
+                    found,
+                    &rejected,
+                    true,
+                ));
+            }
+        }
+    } else {
+        println!("  <disabled by options.icons>");
+    }
+
+    let mut by_origin: BTreeMap<&str, [usize; 2]> = BTreeMap::new();
+    let mut missing = [0; 2];
+    let mut fallbacks = [0; 2];
+    let mut invalid = [0; 2];
+    for record in &records {
+        let level = usize::from(record.entry);
+        invalid[level] += record.invalid;
+        match &record.origin {
+            Some(origin) => by_origin.entry(origin).or_default()[level] += 1,
+            None => missing[level] += 1,
+        }
+        if record.fallback {
+            fallbacks[level] += 1;
+        }
+    }
+    let mut by_origin: Vec<_> = by_origin.into_iter().collect();
+    by_origin.sort_by_key(|(_, counts)| std::cmp::Reverse(counts[0] + counts[1]));
+
+    println!("\nSummary (categories, entries):");
+    for (origin, [categories, entries]) in by_origin {
+        println!("  {}: {}, {}", origin, categories, entries);
+    }
+    println!("  not found: {}, {}", missing[0], missing[1]);
+    println!("  fallback used: {}, {}", fallbacks[0], fallbacks[1]);
+    if config.options.validate_icons {
+        println!("  invalid files skipped: {}, {}", invalid[0], invalid[1]);
+    }
+    Ok(())
+}
+
+/// All menus below `node`, which is at `path`, with their paths.
+fn collect_menus<'a>(node: &'a MenuNode, path: &str, menus: &mut Vec<(String, &'a MenuNode)>) {
+    for (child_path, child) in node.children(path) {
+        collect_menus(child, &child_path, menus);
+        menus.push((child_path, child));
+    }
+}
 
+/// Prints the icon of `label`: the `requested` name, the broken files
+/// skipped on the way, the fallback step and name that found it, if any,
+/// and the file with its theme and size.
+fn print_icon_record(
+    label: &str,
+    requested: Option<&str>,
+    found: Option<(Option<(String, String)>, PathBuf)>,
+    rejected: &[(PathBuf, String)],
+    entry: bool,
+) -> IconRecord {
+    println!("  {}", label);
+    println!(
+        "    Requested: {}",
+        requested
+            .filter(|name| !name.is_empty())
+            .unwrap_or("<none>")
+    );
+    print_rejected(rejected, "    ");
+    let Some((fallback, path)) = found else {
+        println!("    Not found");
+        return IconRecord {
+            entry,
+            origin: None,
+            fallback: false,
+            invalid: rejected.len(),
+        };
+    };
+    if let Some((step, name)) = &fallback {
+        println!("    Fallback: {} '{}'", step, name);
+    }
+    let origin = icon_origin(&path);
+    println!("    Found: {}", path.display());
+    println!("    Source: {}, {}", origin, icon_details(&path));
+    IconRecord {
+        entry,
+        origin: Some(origin),
+        fallback: fallback.is_some(),
+        invalid: rejected.len(),
+    }
+}
+
+/// Prints the icon files dropped by `options.validate_icons`.
+fn print_rejected(rejected: &[(PathBuf, String)], indent: &str) {
+    for (path, reason) in rejected {
+        println!(
+            "{}Skipped invalid icon: {} ({})",
+            indent,
+            path.display(),
+            reason
+        );
+    }
+}
+
+/// Prints a diagnostic report of the entries selected by `action`.
 pub fn list_programs(
     entries: &[DesktopEntry],
     locales: &[String],
+    icons: &dyn IconResolver,
     config: &Config,
     current_desktop: Option<&HashSet<String>>,
     program_name: Option<&str>,
-    action: crate::cli::ListAction,
-) {
+    action: ListAction,
+) -> Result<(), Box<dyn Error>> {
+    match action {
+        ListAction::Theme => {
+            list_theme(config);
+            return Ok(());
+        }
+        ListAction::Icons => return list_icons(entries, locales, icons, config, current_desktop),
+        _ => {}
+    }
     let program_name_filter = program_name.map(|name| name.to_lowercase());
 
     let mut entries: Vec<_> = entries
         .iter()
         .filter(|entry| entry.categories().is_some())
         .filter(|entry| match action {
-            crate::cli::ListAction::All => true,
-            crate::cli::ListAction::MissingIcons => {
+            ListAction::All => true,
+            ListAction::MissingIcons => {
                 let icon_field = entry.icon().unwrap_or_default();
-                !icon_field.is_empty() && entry.icon().and_then(lookup_icon).is_none()
-            }
-            crate::cli::ListAction::Excluded => {
-                visibility_exclusion_reason(entry, current_desktop).is_some()
+                config.options.icons.entries()
+                    && !icon_field.is_empty()
+                    && icon_categories(entry, config, icons, output_categories(entry, config))
+                        .iter()
+                        .any(|category| {
+                            let category_icon = category
+                                .as_deref()
+                                .map(|name| config.icon_for_category(name));
+                            entry_icon(entry, config, icons, category_icon.as_deref()).is_none()
+                        })
             }
-            crate::cli::ListAction::Program => {
+            ListAction::Excluded => visibility_exclusion_reason(entry, current_desktop).is_some(),
+            ListAction::Theme | ListAction::Icons => unreachable!("reported separately"),
+            ListAction::Program => {
                 if let Some(filter_name) = program_name_filter.as_deref() {
                     entry
                         .full_name(locales)
@@ -44,23 +349,36 @@ pub fn list_programs(
     entries.sort_by_key(|entry| entry.full_name(locales).unwrap_or_default());
 
     match action {
-        crate::cli::ListAction::All => println!("Discovered desktop entries:"),
-        crate::cli::ListAction::MissingIcons => {
+        ListAction::All => println!("Discovered desktop entries:"),
+        ListAction::MissingIcons => {
             println!("Desktop entries with missing entry icon lookup:");
+            if !config.options.icons.entries() {
+                println!("Entry icons are disabled by options.icons");
+            }
         }
-        crate::cli::ListAction::Excluded => println!("Hidden/excluded desktop entries:"),
-        crate::cli::ListAction::Program => {
+        ListAction::Excluded => println!("Hidden/excluded desktop entries:"),
+        ListAction::Theme | ListAction::Icons => unreachable!("reported separately"),
+        ListAction::Program => {
             if let Some(name) = program_name {
                 println!("Desktop entries matching Name: {}", name);
             } else {
                 println!("Desktop entries matching Name:");
             }
+            println!(
+                "Icon preference: {}px at scale {}, {} first",
+                config.options.icon_size,
+                config.options.icon_scale,
+                match config.options.icon_format {
+                    IconFormat::Raster => "raster",
+                    IconFormat::Svg => "svg",
+                }
+            );
         }
     }
 
     if entries.is_empty() {
         println!("<none>");
-        return;
+        return Ok(());
     }
 
     for entry in entries {
@@ -68,7 +386,9 @@ pub fn list_programs(
         let desktop_file_path = entry.path.to_string_lossy();
         let exec = entry.exec().unwrap_or_default();
         let icon_field = entry.icon().unwrap_or_default();
-        let entry_icon_path = entry.icon().and_then(lookup_icon);
+        icons.take_rejected();
+        let categories = icon_categories(entry, config, icons, output_categories(entry, config));
+        icons.take_rejected();
         let visibility_reason = visibility_exclusion_reason(entry, current_desktop);
         let excluded_by_filter = visibility_reason.is_some() && config.options.visibility_filter;
 
@@ -83,12 +403,44 @@ pub fn list_programs(
                 &icon_field
             }
         );
-        match entry_icon_path {
-            Some(path) => println!("  Resolved entry icon: {}", path.display()),
-            None if icon_field.is_empty() => {
-                println!("  Entry icon is not defined in the desktop file.")
+        for category in &categories {
+            // Only shown when the icon depends on the output category.
+            let suffix = match category {
+                Some(category) if categories.len() > 1 => format!(" in {}", category),
+                _ => String::new(),
+            };
+            if config.options.icon_fallback.len() > 1 {
+                print_icon_fallback(entry, config, icons, category.as_deref(), &suffix);
+            }
+            let category_icon = category
+                .as_deref()
+                .map(|name| config.icon_for_category(name));
+            match entry_icon(entry, config, icons, category_icon.as_deref()) {
+                Some((choice, _, path)) => {
+                    match choice {
+                        IconChoice::Icon => {
+                            println!("  Resolved entry icon{}: {}", suffix, path.display())
+                        }
+                        IconChoice::Override => println!(
+                            "  Resolved entry icon{}: {} (configured under entries)",
+                            suffix,
+                            path.display()
+                        ),
+                        IconChoice::Fallback(step) => println!(
+                            "  Resolved entry icon{}: {} (fallback: {})",
+                            suffix,
+                            path.display(),
+                            String::from(step)
+                        ),
+                    }
+                    println!("  Entry icon file{}: {}", suffix, icon_details(&path));
+                }
+                None if icon_field.is_empty() => {
+                    println!("  Entry icon is not defined in the desktop file.")
+                }
+                None => println!("  Entry icon lookup failed for '{}'{}.", icon_field, suffix),
             }
-            None => println!("  Entry icon lookup failed for '{}'.", icon_field),
+            print_rejected(&icons.take_rejected(), "  ");
         }
         if let Some(reason) = visibility_reason {
             if config.options.visibility_filter {
@@ -149,7 +501,11 @@ pub fn list_programs(
             if let Some(mapped_category) = config.category_map.get(category) {
                 let output_name = mapped_category.output.as_deref().unwrap_or(category);
                 let category_icon_name = config.icon_for_category(output_name);
-                let category_icon_path = resolve_icon(&category_icon_name);
+                let category_icon_path = resolve_category_icon(
+                    &category_icon_name,
+                    &config.options.icon_fallback,
+                    icons,
+                );
 
                 println!("  Category: {}", category);
                 println!("    Mapped output: {}", output_name);
@@ -160,9 +516,13 @@ pub fn list_programs(
                 }
                 println!("    Category icon: {}", category_icon_name);
                 match category_icon_path {
-                    Some(path) => println!("    Resolved category icon: {}", path.display()),
+                    Some(path) => {
+                        println!("    Resolved category icon: {}", path.display());
+                        println!("    Category icon file: {}", icon_details(&path));
+                    }
                     None => println!("    Category icon lookup failed."),
                 }
+                print_rejected(&icons.take_rejected(), "    ");
             } else {
                 println!("  Category: {} (not mapped)", category);
             }
@@ -174,4 +534,98 @@ pub fn list_programs(
             );
         }
     }
+    Ok(())
+}
+
+/// Which of the output `categories` `entry` is shown in its icon is looked
+/// up for: each one if the `category` step of `options.icon_fallback` is
+/// reached, otherwise only the first, or `None` without any.
+fn icon_categories(
+    entry: &DesktopEntry,
+    config: &Config,
+    icons: &dyn IconResolver,
+    categories: Vec<String>,
+) -> Vec<Option<String>> {
+    let (shared_steps, _) = split_at_category(&config.options.icon_fallback);
+    let shared = || {
+        config
+            .entry_icon(entry.id())
+            .and_then(|icon| icons.resolve(icon))
+            .or_else(|| resolve_entry_icon(entry, shared_steps, None, icons))
+    };
+    if categories.len() <= 1 || shared().is_some() {
+        return vec![categories.into_iter().next()];
+    }
+    categories.into_iter().map(Some).collect()
+}
+
+/// How the icon of an entry was chosen.
+enum IconChoice {
+    /// The `Icon` key of the desktop entry.
+    Icon,
+    /// The icon configured for the entry under `entries`.
+    Override,
+    /// A later step of `options.icon_fallback`.
+    Fallback(IconFallback),
+}
+
+/// The icon configured under `entries` or else found along
+/// `options.icon_fallback` for `entry` shown in a menu with the icon
+/// `category_icon`, with how it was chosen and its name.
+fn entry_icon(
+    entry: &DesktopEntry,
+    config: &Config,
+    icons: &dyn IconResolver,
+    category_icon: Option<&str>,
+) -> Option<(IconChoice, String, PathBuf)> {
+    if let Some(icon) = config.entry_icon(entry.id())
+        && let Some(path) = icons.resolve(icon)
+    {
+        return Some((IconChoice::Override, icon.to_string(), path));
+    }
+    entry_icon_candidates(entry, &config.options.icon_fallback, category_icon)
+        .into_iter()
+        .find_map(|(step, name)| {
+            let name = name?;
+            let path = resolve_candidate(icons, step, &name)?;
+            let choice = match step {
+                IconFallback::Icon => IconChoice::Icon,
+                _ => IconChoice::Fallback(step.clone()),
+            };
+            Some((choice, name, path))
+        })
+}
+
+/// Prints every step of `options.icon_fallback` up to the one that wins for
+/// `entry` shown in the output category `category`, `suffix` naming it.
+fn print_icon_fallback(
+    entry: &DesktopEntry,
+    config: &Config,
+    icons: &dyn IconResolver,
+    category: Option<&str>,
+    suffix: &str,
+) {
+    let category_icon = category.map(|category| config.icon_for_category(category));
+    println!("  Icon fallback{}:", suffix);
+    for (step, name) in entry_icon_candidates(
+        entry,
+        &config.options.icon_fallback,
+        category_icon.as_deref(),
+    ) {
+        let step_name = match step {
+            IconFallback::Named(_) => "name".to_string(),
+            _ => String::from(step.clone()),
+        };
+        let Some(name) = name else {
+            println!("    {}: <none>", step_name);
+            continue;
+        };
+        match resolve_candidate(icons, step, &name) {
+            Some(path) => {
+                println!("    {}: '{}' -> {}", step_name, name, path.display());
+                break;
+            }
+            None => println!("    {}: '{}' not found", step_name, name),
+        }
+    }
 }
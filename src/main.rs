@@ -1,25 +1,22 @@
+use box_menu::builder::MenuBuilder;
+use box_menu::cache::{self, Fingerprint, MenuCache};
+use box_menu::config::{Config, IconLevels, load_config};
+use box_menu::format::{self, OutputFormat};
+use box_menu::icon::{IconCaching, IconResolver};
+use box_menu::list::{ListAction, list_programs};
+use box_menu::menu::Scope;
+use box_menu::source::{EntrySource, SystemEntries};
+use box_menu::timings::Timings;
+use box_menu::visibility::{current_desktop_environment, parse_current_desktop};
+use box_menu::{atomic, export, icon, xdg, xdg_menu};
 use clap::Parser;
-use freedesktop_desktop_entry::{DesktopEntry, desktop_entries, get_languages_from_env};
+use freedesktop_desktop_entry::get_languages_from_env;
+use std::{collections::HashSet, path::PathBuf, time::Duration};
 
 mod cli;
-mod config;
-mod escape;
-mod icon;
-mod list;
-mod menu;
-mod visibility;
-
-use crate::cli::CliOptions;
-use crate::config::load_config;
-use crate::icon::lookup_icon;
-use crate::list::list_programs;
-use crate::menu::Entry;
-use crate::visibility::{
-    current_desktop_environment, parse_current_desktop, visibility_exclusion_reason,
-};
-
-const OPENBOX_XMLNS: &str = "http://openbox.org/";
-const OPENBOX_XSI: &str = "http://www.w3.org/2001/XMLSchema-instance";
+mod watch;
+
+use crate::cli::{CliOptions, Command};
 
 fn main() {
     if let Err(err) = run() {
@@ -28,134 +25,272 @@ fn main() {
     }
 }
 
-fn make_entry(entry: &DesktopEntry, locales: &[String]) -> Entry {
-    Entry {
-        label: escape::escape(entry.full_name(locales).unwrap_or_default()).to_string(),
-        exec: entry.exec().unwrap_or_default().to_string(),
-        icon: entry.icon().and_then(lookup_icon),
-    }
-}
-
 fn run() -> Result<(), Box<dyn std::error::Error>> {
     let cli_options = CliOptions::parse();
+    let mut timings = Timings::new(cli_options.timings());
     let cfg = load_config(cli_options.config_file())?;
 
-    if let Some(theme) = cfg.options.icon_theme.clone() {
-        crate::icon::set_theme(theme);
+    if let Some(Command::ExportXdgMenu { output_dir }) = cli_options.command() {
+        let output_dir = output_dir
+            .clone()
+            .unwrap_or_else(|| xdg::config_home().join("menus"));
+        for path in export::export_xdg_menu(&cfg, &output_dir)? {
+            println!("Wrote {}", path.display());
+        }
+        return Ok(());
+    }
+
+    if let Some(Command::Watch {
+        reconfigure,
+        debounce,
+    }) = cli_options.command()
+    {
+        let output = cli_options.output().ok_or_else(|| {
+            clap::Error::raw(
+                clap::error::ErrorKind::MissingRequiredArgument,
+                "--output is required for watch",
+            )
+        })?;
+        let config_file = config_path(&cli_options);
+        return watch::watch(
+            output,
+            Duration::from_millis(*debounce),
+            *reconfigure,
+            || {
+                let options = load_config(cli_options.config_file())
+                    .map(|cfg| cfg.options)
+                    .unwrap_or_default();
+                watch::watched_dirs(
+                    config_file.as_deref(),
+                    options.xdg_menu.as_deref(),
+                    &icon::themes_for(&options),
+                )
+            },
+            || {
+                let cfg = load_config(cli_options.config_file())?;
+                let locales = get_languages_from_env();
+                let current_desktop = current_desktop_environment();
+                let current_desktop_parsed = current_desktop.as_deref().map(parse_current_desktop);
+                let mut timings = Timings::new(cli_options.timings());
+                let menu = generate_menu(
+                    &cli_options,
+                    &cfg,
+                    &locales,
+                    current_desktop_parsed.as_ref(),
+                    &mut timings,
+                );
+                timings.report();
+                menu
+            },
+        );
     }
 
     let locales = get_languages_from_env();
     let current_desktop = current_desktop_environment();
+
+    let menu_cache = (cli_options.list_action().is_none() && !cli_options.no_cache()).then(|| {
+        let fingerprint =
+            menu_fingerprint(&cli_options, &cfg, &locales, current_desktop.as_deref());
+        MenuCache::new(&cache::cache_dir(), &cli_options.cache_key(), &fingerprint)
+    });
+    if !cli_options.rebuild_cache()
+        && let Some(menu) = menu_cache.as_ref().and_then(MenuCache::load)
+    {
+        write_output(&cli_options, &menu)?;
+        timings.report();
+        return Ok(());
+    }
+
     let current_desktop_parsed = current_desktop.as_deref().map(parse_current_desktop);
-    let all_entries: Vec<DesktopEntry> = desktop_entries(&locales).into_iter().collect();
-    let program_name = cli_options.program_name();
 
     if let Some(action) = cli_options.list_action() {
-        if matches!(action, crate::cli::ListAction::Program) && program_name.is_none() {
+        let all_entries = match action {
+            ListAction::Theme => Vec::new(),
+            _ => SystemEntries.entries(&locales, &mut timings),
+        };
+        let program_name = cli_options.program_name();
+        if matches!(action, ListAction::Program) && program_name.is_none() {
             return Err(Box::new(clap::Error::raw(
                 clap::error::ErrorKind::MissingRequiredArgument,
                 "NAME is required when --list program is used",
             )));
         }
-        if !matches!(action, crate::cli::ListAction::Program) && program_name.is_some() {
+        if !matches!(action, ListAction::Program) && program_name.is_some() {
             return Err(Box::new(clap::Error::raw(
                 clap::error::ErrorKind::ArgumentConflict,
                 "NAME can only be used with --list program",
             )));
         }
 
+        let icons = icon::configured_icons(&cfg.options, icon_caching(&cli_options, &cfg));
         list_programs(
             &all_entries,
             &locales,
+            &*icons,
             &cfg,
             current_desktop_parsed.as_ref(),
             program_name,
             action,
-        );
+        )?;
+        save_icon_cache(&*icons);
         return Ok(());
     }
 
-    let mut excluded_entries = Vec::new();
-    let entries: Vec<&DesktopEntry> = all_entries
-        .iter()
-        .filter(|x| x.categories().is_some())
-        .filter(|x| {
-            if !cfg.options.visibility_filter {
-                return true;
-            }
+    let menu = generate_menu(
+        &cli_options,
+        &cfg,
+        &locales,
+        current_desktop_parsed.as_ref(),
+        &mut timings,
+    )?;
+    if let Some(menu_cache) = &menu_cache
+        && let Err(err) = menu_cache.store(&menu)
+    {
+        eprintln!("warning: failed to update menu cache: {}", err);
+    }
+    write_output(&cli_options, &menu)?;
+    timings.report();
+    Ok(())
+}
 
-            if let Some(reason) = visibility_exclusion_reason(x, current_desktop_parsed.as_ref()) {
-                let label = x.full_name(&locales).unwrap_or_default().to_string();
-                excluded_entries.push((label, reason));
-                false
-            } else {
-                true
-            }
-        })
-        .collect();
-
-    let mut root = cfg.empty_tree();
-    for entry in entries {
-        let mapped_categories: Vec<String> = entry
-            .categories()
-            .unwrap()
-            .into_iter()
-            .filter(|k| !k.is_empty())
-            .filter(|k| cfg.category_map.contains_key(&k[..]))
-            .map(|k| k.to_string())
-            .collect();
-
-        if mapped_categories.is_empty() {
-            continue;
-        }
+/// Builds the menu tree and renders it as requested by `cli_options`.
+fn generate_menu(
+    cli_options: &CliOptions,
+    cfg: &Config,
+    locales: &[String],
+    current_desktop: Option<&HashSet<String>>,
+    timings: &mut Timings,
+) -> Result<String, Box<dyn std::error::Error>> {
+    if cli_options.static_menu() && !matches!(cli_options.format(), OutputFormat::Openbox) {
+        return Err(Box::new(clap::Error::raw(
+            clap::error::ErrorKind::ArgumentConflict,
+            "--static and --template require --format openbox",
+        )));
+    }
+    if cli_options.lazy() && !matches!(cli_options.format(), OutputFormat::Openbox) {
+        return Err(Box::new(clap::Error::raw(
+            clap::error::ErrorKind::ArgumentConflict,
+            "--lazy requires --format openbox",
+        )));
+    }
+    if cli_options.static_menu() && (cli_options.lazy() || cli_options.submenu().is_some()) {
+        return Err(Box::new(clap::Error::raw(
+            clap::error::ErrorKind::ArgumentConflict,
+            "--static cannot be combined with --lazy or --submenu",
+        )));
+    }
+    let scope = Scope::new(cli_options.submenu(), cli_options.lazy());
+    let all_entries = SystemEntries.entries(locales, timings);
+    let icons = icon::configured_icons(&cfg.options, icon_caching(cli_options, cfg));
+    let builder = MenuBuilder::new(cfg)
+        .locales(locales)
+        .icons(&*icons)
+        .current_desktop(current_desktop)
+        .scope(scope);
+    let menu = builder.build_with_timings(&all_entries, timings)?;
+    let path = cli_options.submenu().unwrap_or_default().trim_matches('/');
+    let node = menu
+        .root
+        .subtree(path)
+        .ok_or_else(|| format!("no menu at submenu path '{}'", path))?;
 
-        let menu_entry = make_entry(entry, &locales);
-
-        if cfg.options.category_priority {
-            let entries_category = mapped_categories
-                .into_iter()
-                .map(|c| {
-                    let c_str: &str = c.as_ref();
-                    let mapped_category = cfg.category_map.get(c_str).unwrap();
-                    let output_name = mapped_category.output.as_deref().unwrap_or(c_str);
-                    let priority = mapped_category.priority.unwrap_or(0);
-                    (priority, output_name.to_string())
-                })
-                .max_by(|a, b| a.0.cmp(&b.0).then(a.1.cmp(&b.1)))
-                .map(|(_, output_name)| output_name)
-                .unwrap();
-
-            root.insert(&entries_category, menu_entry);
+    let menu = timings.time("render", || {
+        Ok(if cli_options.static_menu() {
+            let template = cli_options
+                .template()
+                .map(std::fs::read_to_string)
+                .transpose()?;
+            let favorites = builder.favorites(&all_entries);
+            format::render_static(&menu.root, cfg, &*icons, template.as_deref(), &favorites)?
+        } else if cli_options.lazy() {
+            format::render_lazy(node, path, cfg, &*icons, &menu.excluded, &|submenu| {
+                cli_options.submenu_command(submenu)
+            })
         } else {
-            // Insert into every matching mapped category by default.
-            for c in mapped_categories {
-                let c_str: &str = c.as_ref();
-                let mapped_category = cfg.category_map.get(c_str).unwrap();
-                let output_name = mapped_category
-                    .output
-                    .clone()
-                    .unwrap_or_else(|| c.to_string());
-                root.insert(&output_name, menu_entry.clone());
-            }
-        }
-    }
+            format::render(
+                cli_options.format(),
+                node,
+                path,
+                cfg,
+                &*icons,
+                &menu.excluded,
+            )
+        })
+    });
+    save_icon_cache(&*icons);
+    menu
+}
 
-    println!("<?xml version=\"1.0\" encoding=\"UTF-8\"?>");
-    println!(
-        "<openbox_menu xmlns=\"{}\" xmlns:xsi=\"{}\" xsi:schemaLocation=\"{}\" >",
-        OPENBOX_XMLNS, OPENBOX_XSI, OPENBOX_XMLNS
-    );
-    root.print(&cfg, "");
-    println!("</openbox_menu>");
+/// Where icon lookups are kept: in the cache directory unless disabled,
+/// otherwise in memory, except while watching, where icons may be installed
+/// between two menus.
+fn icon_caching(cli_options: &CliOptions, cfg: &Config) -> IconCaching {
+    if !cli_options.no_cache() && cfg.options.icons != IconLevels::None {
+        IconCaching::Disk
+    } else if matches!(cli_options.command(), Some(Command::Watch { .. })) {
+        IconCaching::None
+    } else {
+        IconCaching::Memory
+    }
+}
 
-    if !excluded_entries.is_empty() {
-        println!("<!-- Excluded entries (visibility filtering):");
-        for (label, reason) in excluded_entries {
-            let comment_line = format!("  {} ({})", label, reason).replace("--", "—");
-            println!("{}", comment_line);
-        }
-        println!("-->");
+fn save_icon_cache(icons: &dyn IconResolver) {
+    if let Err(err) = icons.save_cache() {
+        eprintln!("warning: failed to update icon cache: {}", err);
     }
+}
 
+fn write_output(cli_options: &CliOptions, menu: &str) -> Result<(), Box<dyn std::error::Error>> {
+    match cli_options.output() {
+        Some(path) => atomic::write_file(path, menu.as_bytes())?,
+        None => print!("{}", menu),
+    }
     Ok(())
 }
+
+/// The configuration file in use, explicit or the default XDG location.
+fn config_path(cli_options: &CliOptions) -> Option<PathBuf> {
+    match cli_options.config_file() {
+        Some(path) => Some(path.clone()),
+        None => confy::get_configuration_file_path("box-menu-rs", "config").ok(),
+    }
+}
+
+/// Everything besides the command line that influences the generated menu.
+fn menu_fingerprint(
+    cli_options: &CliOptions,
+    cfg: &Config,
+    locales: &[String],
+    current_desktop: Option<&str>,
+) -> Fingerprint {
+    let mut fingerprint = Fingerprint::new();
+    if let Some(path) = config_path(cli_options) {
+        fingerprint.file(&path);
+    }
+    if let Some(template) = cli_options.template() {
+        fingerprint.file(template);
+    }
+    if let Some(menu_file) = &cfg.options.xdg_menu {
+        match xdg_menu::menu_sources(menu_file) {
+            Ok(sources) => {
+                for file in &sources.files {
+                    fingerprint.file(file);
+                }
+                for dir in &sources.dirs {
+                    fingerprint.tree(dir);
+                }
+            }
+            // Building the menu fails as well, so nothing is cached.
+            Err(_) => fingerprint.file(menu_file),
+        }
+    }
+    for dir in xdg::application_dirs() {
+        fingerprint.tree(&dir);
+    }
+    fingerprint.value("locales", &locales.join(":"));
+    if cfg.options.icons != IconLevels::None {
+        fingerprint.value("icon_theme", &icon::themes_for(&cfg.options).join(":"));
+    }
+    fingerprint.value("current_desktop", current_desktop.unwrap_or_default());
+    fingerprint
+}
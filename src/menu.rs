@@ -1,44 +1,210 @@
-use crate::{config::Config, escape, icon::resolve_icon};
+use crate::{
+    config::{Config, IconFallback},
+    icon::{IconResolver, resolve_entry_icon, split_at_category},
+};
+use freedesktop_desktop_entry::DesktopEntry;
 use std::{
+    cmp::Ordering,
     collections::{BTreeMap, BTreeSet},
-    fmt,
+    hash::{Hash, Hasher},
     path::PathBuf,
 };
 
-#[derive(Clone, Eq, Hash, PartialEq, PartialOrd, Ord)]
+/// A menu item. Entries are compared by label, command and icon only, so
+/// that desktop files showing the same item are listed once.
+#[derive(Clone)]
 pub struct Entry {
     pub label: String,
     pub exec: String,
     pub icon: Option<PathBuf>,
+    /// Desktop file id of the entry, see [`DesktopEntry::id`].
+    pub id: String,
+}
+
+impl Entry {
+    fn key(&self) -> (&str, &str, Option<&PathBuf>) {
+        (&self.label, &self.exec, self.icon.as_ref())
+    }
+}
+
+impl PartialEq for Entry {
+    fn eq(&self, other: &Self) -> bool {
+        self.key() == other.key()
+    }
+}
+
+impl Eq for Entry {}
+
+impl Hash for Entry {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.key().hash(state);
+    }
+}
+
+impl PartialOrd for Entry {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Entry {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.key().cmp(&other.key())
+    }
 }
 
-impl fmt::Display for Entry {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        let icon_attr = self
-            .icon
-            .as_ref()
-            .map(|p| format!(" icon=\"{}\"", p.display()))
-            .unwrap_or_default();
-        write!(
-            f,
-            "<item label=\"{}\"{}><action name=\"Execute\"><command>{}</command></action></item>",
-            self.label, icon_attr, self.exec,
-        )
+/// Converts a desktop entry, using the icon configured for it under
+/// `entries` or else finding one along `options.icon_fallback`, unless
+/// `options.icons` leaves out entry icons.
+pub fn make_entry(
+    entry: &DesktopEntry,
+    locales: &[String],
+    config: &Config,
+    icons: &dyn IconResolver,
+) -> Entry {
+    let icon = if config.options.icons.entries() {
+        find_entry_icon(entry, config, &config.options.icon_fallback, icons)
+    } else {
+        None
+    };
+    entry_with_icon(entry, locales, icon)
+}
+
+/// Like [`make_entry`] for an entry shown in the output category at `path`,
+/// whose icon is `category_icon`, but skips the icon lookup for entries
+/// outside `scope` which only contribute to the menu structure.
+pub fn make_scoped_entry(
+    entry: &DesktopEntry,
+    locales: &[String],
+    config: &Config,
+    icons: &dyn IconResolver,
+    scope: &Scope,
+    path: &str,
+    category_icon: &str,
+) -> Entry {
+    let menus = [(path, category_icon.to_string())];
+    let mut entries = make_scoped_entries(entry, locales, config, icons, scope, &menus);
+    entries.remove(0)
+}
+
+/// Like [`make_scoped_entry`] for an entry shown in several output
+/// categories, one entry per `(path, category icon)` of `menus`. The steps
+/// of `options.icon_fallback` before `category` are looked up once, the rest
+/// for each category, so that the `category` step uses the icon of the
+/// category the entry is shown in.
+pub fn make_scoped_entries(
+    entry: &DesktopEntry,
+    locales: &[String],
+    config: &Config,
+    icons: &dyn IconResolver,
+    scope: &Scope,
+    menus: &[(&str, String)],
+) -> Vec<Entry> {
+    let (shared_steps, category_steps) = split_at_category(&config.options.icon_fallback);
+    let mut shared = None;
+    menus
+        .iter()
+        .map(|(path, category_icon)| {
+            let icon = if config.options.icons.entries() && scope.includes(path) {
+                shared
+                    .get_or_insert_with(|| find_entry_icon(entry, config, shared_steps, icons))
+                    .clone()
+                    .or_else(|| {
+                        resolve_entry_icon(entry, category_steps, Some(category_icon), icons)
+                    })
+            } else {
+                None
+            };
+            entry_with_icon(entry, locales, icon)
+        })
+        .collect()
+}
+
+/// The icon configured for `entry` under `entries`, or else the first one
+/// found along `fallback` without a category icon.
+fn find_entry_icon(
+    entry: &DesktopEntry,
+    config: &Config,
+    fallback: &[IconFallback],
+    icons: &dyn IconResolver,
+) -> Option<PathBuf> {
+    config
+        .entry_icon(entry.id())
+        .and_then(|icon| icons.resolve(icon))
+        .or_else(|| resolve_entry_icon(entry, fallback, None, icons))
+}
+
+fn entry_with_icon(entry: &DesktopEntry, locales: &[String], icon: Option<PathBuf>) -> Entry {
+    Entry {
+        label: entry.full_name(locales).unwrap_or_default().to_string(),
+        exec: entry.exec().unwrap_or_default().to_string(),
+        icon,
+        id: entry.id().to_string(),
+    }
+}
+
+/// Part of the menu tree rendered by this invocation, used to skip icon
+/// lookups for entries that are not printed.
+pub struct Scope {
+    path: String,
+    lazy: bool,
+}
+
+impl Scope {
+    /// `path` selects a subtree (`None` for the whole menu); with `lazy` only
+    /// the entries directly in it are printed, submenus become stubs.
+    pub fn new(path: Option<&str>, lazy: bool) -> Self {
+        Self {
+            path: path.unwrap_or_default().trim_matches('/').to_string(),
+            lazy,
+        }
+    }
+
+    /// Whether entries of the output category at `path` are printed.
+    pub fn includes(&self, path: &str) -> bool {
+        let path = path.trim_matches('/');
+        if self.lazy {
+            return path == self.path;
+        }
+        self.path.is_empty()
+            || path == self.path
+            || path
+                .strip_prefix(self.path.as_str())
+                .is_some_and(|rest| rest.starts_with('/'))
     }
 }
 
+/// Explicit ordering of a menu's content, e.g. from an XDG `<Layout>`.
+pub enum LayoutItem {
+    Menu(String),
+    Entry(Entry),
+    Separator,
+}
+
+/// One item of a menu as seen by the output writers.
+pub enum MenuItem<'a> {
+    /// A submenu together with its full slash-separated output path.
+    Menu(String, &'a MenuNode),
+    Entry(&'a Entry),
+    Separator,
+}
+
 pub struct MenuNode {
     label: String,
+    icon: Option<String>,
     children: BTreeMap<String, MenuNode>,
     entries: BTreeSet<Entry>,
+    layout: Option<Vec<LayoutItem>>,
 }
 
 impl MenuNode {
     pub fn new(label: String) -> Self {
         Self {
             label,
+            icon: None,
             children: BTreeMap::new(),
             entries: BTreeSet::new(),
+            layout: None,
         }
     }
 
@@ -57,39 +223,125 @@ impl MenuNode {
         self.node_for_path(path).entries.insert(entry);
     }
 
-    pub fn print(&self, config: &Config, path: &str) {
-        if !self.label.is_empty() {
-            let category_icon_name = config.icon_for_category(path);
-            let icon_str = resolve_icon(&category_icon_name)
-                .map(|p| format!(" icon=\"{}\"", p.display()))
-                .unwrap_or_default();
-            println!(
-                "<menu id=\"boxmenu-{}\" label=\"{}\"{}>",
-                Self::menu_id(path),
-                escape::escape(&self.label),
-                icon_str
-            );
-        }
+    /// Adds `child` as submenu `name`, replacing a previous one.
+    pub fn add_child(&mut self, name: String, child: MenuNode) {
+        self.children.insert(name, child);
+    }
 
-        for (child_name, child) in &self.children {
-            let child_path = if path.is_empty() {
-                child_name.clone()
-            } else {
-                format!("{}/{}", path, child_name)
-            };
-            child.print(config, &child_path);
+    /// The node at the slash-separated `path` below this one.
+    pub fn subtree(&self, path: &str) -> Option<&MenuNode> {
+        path.split('/')
+            .filter(|segment| !segment.is_empty())
+            .try_fold(self, |node, segment| node.children.get(segment))
+    }
+
+    pub fn label(&self) -> &str {
+        &self.label
+    }
+
+    /// Icon name or path from the menu source, e.g. a `.directory` file.
+    pub fn icon(&self) -> Option<&str> {
+        self.icon.as_deref()
+    }
+
+    pub fn set_icon(&mut self, icon: Option<String>) {
+        self.icon = icon;
+    }
+
+    /// Orders the menu explicitly; children and entries not referenced by
+    /// `layout` are not shown.
+    pub fn set_layout(&mut self, layout: Vec<LayoutItem>) {
+        for item in &layout {
+            if let LayoutItem::Entry(entry) = item {
+                self.entries.insert(entry.clone());
+            }
         }
+        self.layout = Some(layout);
+    }
+
+    /// Child menus together with their full slash-separated output path.
+    pub fn children<'a>(
+        &'a self,
+        path: &str,
+    ) -> impl Iterator<Item = (String, &'a MenuNode)> + use<'a> {
+        let path = path.to_string();
+        self.children
+            .iter()
+            .map(move |(child_name, child)| (Self::child_path(&path, child_name), child))
+    }
 
-        for entry in &self.entries {
-            println!("{}", entry);
+    pub fn entries(&self) -> impl Iterator<Item = &Entry> {
+        self.entries.iter()
+    }
+
+    /// Menu content in display order: the explicit layout if one was set,
+    /// otherwise all submenus followed by all entries.
+    pub fn items<'a>(&'a self, path: &str) -> Vec<MenuItem<'a>> {
+        match &self.layout {
+            Some(layout) => layout
+                .iter()
+                .filter_map(|item| match item {
+                    LayoutItem::Menu(name) => self
+                        .children
+                        .get(name)
+                        .map(|child| MenuItem::Menu(Self::child_path(path, name), child)),
+                    LayoutItem::Entry(entry) => Some(MenuItem::Entry(entry)),
+                    LayoutItem::Separator => Some(MenuItem::Separator),
+                })
+                .collect(),
+            None => self
+                .children
+                .iter()
+                .map(|(name, child)| MenuItem::Menu(Self::child_path(path, name), child))
+                .chain(self.entries().map(MenuItem::Entry))
+                .collect(),
         }
+    }
 
-        if !self.label.is_empty() {
-            println!("</menu>");
+    fn child_path(path: &str, child_name: &str) -> String {
+        if path.is_empty() {
+            child_name.to_string()
+        } else {
+            format!("{}/{}", path, child_name)
         }
     }
 
-    fn menu_id(path: &str) -> String {
+    pub fn menu_id(path: &str) -> String {
         path.replace(['/', ' '], "-")
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn scope_includes_subtree_or_only_lazy_level() {
+        let full = Scope::new(None, false);
+        assert!(full.includes("Apps/Office"));
+
+        let subtree = Scope::new(Some("/Apps/"), false);
+        assert!(subtree.includes("Apps"));
+        assert!(subtree.includes("Apps/Office"));
+        assert!(!subtree.includes("Applications"));
+        assert!(!subtree.includes("Graphics"));
+
+        let lazy = Scope::new(Some("Apps"), true);
+        assert!(lazy.includes("Apps"));
+        assert!(!lazy.includes("Apps/Office"));
+        assert!(Scope::new(None, true).includes(""));
+        assert!(!Scope::new(None, true).includes("Apps"));
+    }
+
+    #[test]
+    fn subtree_follows_path_segments() {
+        let mut root = MenuNode::new(String::new());
+        root.node_for_path("Apps/Office");
+        assert_eq!(root.subtree("").map(MenuNode::label), Some(""));
+        assert_eq!(
+            root.subtree("Apps/Office").map(MenuNode::label),
+            Some("Office")
+        );
+        assert!(root.subtree("Apps/Games").is_none());
+    }
+}
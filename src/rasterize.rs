@@ -0,0 +1,225 @@
+use crate::{atomic, cache, config::Options, icon::IconResolver};
+use resvg::{tiny_skia, usvg};
+use std::{
+    collections::hash_map::DefaultHasher,
+    error::Error,
+    fs,
+    hash::{Hash, Hasher},
+    io,
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+/// Icons for window managers that cannot render SVG, e.g. Openbox built
+/// without librsvg.
+///
+/// Wraps another resolver and replaces the SVG (or gzip compressed `.svgz`)
+/// files it finds by PNGs of
+/// `pixels` square, rendered once into `dir` and again whenever the SVG
+/// file is newer than its PNG. SVGs that fail to render are kept.
+pub struct RasterizedIcons<R> {
+    inner: R,
+    pixels: u32,
+    dir: PathBuf,
+    /// Serializes rendering, so that entries sharing an icon do not write
+    /// the same PNG at once.
+    lock: Mutex<()>,
+}
+
+impl<R: IconResolver> RasterizedIcons<R> {
+    pub fn new(inner: R, pixels: u32, dir: impl Into<PathBuf>) -> Self {
+        Self {
+            inner,
+            pixels,
+            dir: dir.into(),
+            lock: Mutex::new(()),
+        }
+    }
+
+    /// Renders at `icon_size` times `icon_scale` of `options` into
+    /// `$XDG_CACHE_HOME/box-menu-rs/icons`.
+    pub fn from_options(inner: R, options: &Options) -> Self {
+        let pixels = u32::from(options.icon_size) * u32::from(options.icon_scale);
+        Self::new(inner, pixels, cache::cache_dir().join("icons"))
+    }
+
+    fn raster(&self, path: PathBuf) -> PathBuf {
+        let is_svg = path.extension().is_some_and(|extension| {
+            extension.eq_ignore_ascii_case("svg") || extension.eq_ignore_ascii_case("svgz")
+        });
+        if !is_svg {
+            return path;
+        }
+        let _guard = self.lock.lock().unwrap();
+        match self.cached_png(&path) {
+            Ok(png) => png,
+            Err(err) => {
+                eprintln!(
+                    "warning: failed to rasterize icon {}: {}",
+                    path.display(),
+                    err
+                );
+                path
+            }
+        }
+    }
+
+    /// The PNG for `svg`, rendered unless an up to date one exists.
+    fn cached_png(&self, svg: &Path) -> Result<PathBuf, Box<dyn Error>> {
+        let mut hasher = DefaultHasher::new();
+        svg.hash(&mut hasher);
+        let stem = svg.file_stem().unwrap_or_default().to_string_lossy();
+        let png = self.dir.join(format!(
+            "{}-{}-{:016x}.png",
+            stem,
+            self.pixels,
+            hasher.finish()
+        ));
+
+        let modified = |path: &Path| fs::metadata(path).and_then(|m| m.modified());
+        let svg_modified = modified(svg)?;
+        if modified(&png).is_ok_and(|png_modified| png_modified >= svg_modified) {
+            return Ok(png);
+        }
+        atomic::write_file(&png, &render(svg, self.pixels)?)?;
+        Ok(png)
+    }
+}
+
+impl<R: IconResolver> IconResolver for RasterizedIcons<R> {
+    fn lookup(&self, name: &str) -> Option<PathBuf> {
+        self.inner.lookup(name).map(|path| self.raster(path))
+    }
+
+    fn resolve(&self, name_or_path: &str) -> Option<PathBuf> {
+        self.inner
+            .resolve(name_or_path)
+            .map(|path| self.raster(path))
+    }
+
+    fn take_rejected(&self) -> Vec<(PathBuf, String)> {
+        self.inner.take_rejected()
+    }
+
+    fn save_cache(&self) -> io::Result<()> {
+        self.inner.save_cache()
+    }
+}
+
+/// Renders the SVG file `svg` centered into a PNG of `pixels` square; usvg
+/// decompresses `.svgz` data itself.
+fn render(svg: &Path, pixels: u32) -> Result<Vec<u8>, Box<dyn Error>> {
+    let options = usvg::Options {
+        resources_dir: svg.parent().map(Path::to_path_buf),
+        ..usvg::Options::default()
+    };
+    let tree = usvg::Tree::from_data(&fs::read(svg)?, &options)?;
+    let mut pixmap = tiny_skia::Pixmap::new(pixels, pixels).ok_or("invalid icon size")?;
+
+    let size = tree.size();
+    let scale = pixels as f32 / size.width().max(size.height());
+    let transform = tiny_skia::Transform::from_scale(scale, scale).post_translate(
+        (pixels as f32 - size.width() * scale) / 2.0,
+        (pixels as f32 - size.height() * scale) / 2.0,
+    );
+    resvg::render(&tree, transform, &mut pixmap.as_mut());
+    Ok(pixmap.encode_png()?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::icon::DirectoryIcons;
+    use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+    const SVG: &str = r##"<svg xmlns="http://www.w3.org/2000/svg" width="16" height="8"><rect width="16" height="8" fill="#3465a4"/></svg>"##;
+
+    #[test]
+    fn svg_icons_are_rendered_and_cached() {
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .expect("time went backwards")
+            .as_nanos();
+        let dir = std::env::temp_dir().join(format!("box-menu-rs-test-rasterize-{}", timestamp));
+        let icons_dir = dir.join("icons");
+        fs::create_dir_all(&icons_dir).expect("failed to create temp dir");
+        fs::write(icons_dir.join("editor.svg"), SVG).expect("failed to write icon");
+        fs::write(icons_dir.join("player.png"), b"png").expect("failed to write icon");
+
+        let icons = RasterizedIcons::new(DirectoryIcons::new(&icons_dir), 32, dir.join("cache"));
+        let png = icons.lookup("editor").expect("icon not found");
+        assert_eq!(png.parent(), Some(dir.join("cache").as_path()));
+        assert_eq!(png.extension().unwrap(), "png");
+        assert!(fs::read(&png).unwrap().starts_with(b"\x89PNG"));
+        assert_eq!(icons.lookup("player"), Some(icons_dir.join("player.png")));
+
+        // An up to date PNG is reused, a newer SVG renders it again.
+        let old = SystemTime::now() - Duration::from_secs(60);
+        fs::File::options()
+            .write(true)
+            .open(&png)
+            .and_then(|file| file.set_modified(old))
+            .expect("failed to set mtime");
+        fs::File::options()
+            .write(true)
+            .open(icons_dir.join("editor.svg"))
+            .and_then(|file| file.set_modified(old - Duration::from_secs(60)))
+            .expect("failed to set mtime");
+        assert_eq!(icons.lookup("editor"), Some(png.clone()));
+        assert_eq!(fs::metadata(&png).unwrap().modified().unwrap(), old);
+
+        fs::write(icons_dir.join("editor.svg"), SVG).expect("failed to write icon");
+        assert_eq!(icons.lookup("editor"), Some(png.clone()));
+        assert!(fs::metadata(&png).unwrap().modified().unwrap() > old);
+
+        fs::remove_dir_all(&dir).expect("failed to remove temp dir");
+    }
+
+    #[test]
+    fn svgz_icons_are_rendered() {
+        use flate2::{Compression, write::GzEncoder};
+        use std::io::Write;
+
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .expect("time went backwards")
+            .as_nanos();
+        let dir =
+            std::env::temp_dir().join(format!("box-menu-rs-test-rasterize-svgz-{}", timestamp));
+        fs::create_dir_all(&dir).expect("failed to create temp dir");
+        let svgz = dir.join("editor.svgz");
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder
+            .write_all(SVG.as_bytes())
+            .expect("failed to compress icon");
+        fs::write(&svgz, encoder.finish().expect("failed to compress icon"))
+            .expect("failed to write icon");
+
+        let icons = RasterizedIcons::new(DirectoryIcons::new(&dir), 32, dir.join("cache"));
+        let png = icons
+            .resolve(svgz.to_str().unwrap())
+            .expect("icon not found");
+        assert_eq!(png.parent(), Some(dir.join("cache").as_path()));
+        assert!(fs::read(&png).unwrap().starts_with(b"\x89PNG"));
+
+        fs::remove_dir_all(&dir).expect("failed to remove temp dir");
+    }
+
+    #[test]
+    fn broken_svg_icons_are_kept() {
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .expect("time went backwards")
+            .as_nanos();
+        let dir =
+            std::env::temp_dir().join(format!("box-menu-rs-test-rasterize-broken-{}", timestamp));
+        fs::create_dir_all(&dir).expect("failed to create temp dir");
+        let svg = dir.join("broken.svg");
+        fs::write(&svg, b"not an svg").expect("failed to write icon");
+
+        let icons = RasterizedIcons::new(DirectoryIcons::new(&dir), 32, dir.join("cache"));
+        assert_eq!(icons.resolve(svg.to_str().unwrap()), Some(svg.clone()));
+
+        fs::remove_dir_all(&dir).expect("failed to remove temp dir");
+    }
+}
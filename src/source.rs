@@ -0,0 +1,127 @@
+use crate::{parallel, timings::Timings};
+use freedesktop_desktop_entry::{DesktopEntry, Iter, default_paths};
+use std::path::PathBuf;
+
+/// Provides the desktop entries a menu is built from.
+pub trait EntrySource {
+    /// Parses the entries with the names and comments of `locales`,
+    /// recording the phases in `timings`.
+    fn entries(&self, locales: &[String], timings: &mut Timings) -> Vec<DesktopEntry>;
+}
+
+/// The `applications` directories below `$XDG_DATA_HOME` and
+/// `$XDG_DATA_DIRS`.
+pub struct SystemEntries;
+
+impl EntrySource for SystemEntries {
+    fn entries(&self, locales: &[String], timings: &mut Timings) -> Vec<DesktopEntry> {
+        let paths: Vec<PathBuf> = timings.time("scan", || Iter::new(default_paths()).collect());
+        parse_files(&paths, locales, timings)
+    }
+}
+
+/// Desktop files found recursively in explicitly given directories.
+pub struct DirectoryEntries {
+    dirs: Vec<PathBuf>,
+}
+
+impl DirectoryEntries {
+    pub fn new(dirs: impl IntoIterator<Item = PathBuf>) -> Self {
+        Self {
+            dirs: dirs.into_iter().collect(),
+        }
+    }
+}
+
+impl EntrySource for DirectoryEntries {
+    fn entries(&self, locales: &[String], timings: &mut Timings) -> Vec<DesktopEntry> {
+        let paths: Vec<PathBuf> =
+            timings.time("scan", || Iter::new(self.dirs.iter().cloned()).collect());
+        parse_files(&paths, locales, timings)
+    }
+}
+
+/// Desktop files held in memory, e.g. fixtures in tests.
+#[derive(Default)]
+pub struct MemoryEntries {
+    files: Vec<(PathBuf, String)>,
+}
+
+impl MemoryEntries {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a desktop file; its id is derived from the file name of `path`.
+    pub fn with(mut self, path: impl Into<PathBuf>, contents: impl Into<String>) -> Self {
+        self.files.push((path.into(), contents.into()));
+        self
+    }
+}
+
+impl EntrySource for MemoryEntries {
+    fn entries(&self, locales: &[String], timings: &mut Timings) -> Vec<DesktopEntry> {
+        timings.time("parse", || {
+            self.files
+                .iter()
+                .filter_map(|(path, contents)| {
+                    DesktopEntry::from_str(path, contents, Some(locales)).ok()
+                })
+                .collect()
+        })
+    }
+}
+
+/// Parses the desktop files on a worker pool, keeping the order of `paths`.
+fn parse_files(paths: &[PathBuf], locales: &[String], timings: &mut Timings) -> Vec<DesktopEntry> {
+    timings.time("parse", || {
+        parallel::map(paths, |path| {
+            DesktopEntry::from_path(path.clone(), Some(locales)).ok()
+        })
+        .into_iter()
+        .flatten()
+        .collect()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::time::{SystemTime, UNIX_EPOCH};
+
+    const EDITOR: &str = "[Desktop Entry]\nType=Application\nName=Editor\nName[de]=Bearbeiter\nExec=editor\nCategories=Utility;\n";
+
+    #[test]
+    fn memory_entries_are_parsed_with_locales() {
+        let source = MemoryEntries::new()
+            .with("/fixtures/editor.desktop", EDITOR)
+            .with("/fixtures/broken.desktop", "not a desktop file");
+        let locales = vec!["de".to_string()];
+        let entries = source.entries(&locales, &mut Timings::new(false));
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].id(), "editor");
+        assert_eq!(
+            entries[0].full_name(&locales).as_deref(),
+            Some("Bearbeiter")
+        );
+    }
+
+    #[test]
+    fn directory_entries_scan_subdirectories() {
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .expect("time went backwards")
+            .as_nanos();
+        let dir = std::env::temp_dir().join(format!("box-menu-rs-test-source-{}", timestamp));
+        fs::create_dir_all(dir.join("vendor")).expect("failed to create temp dir");
+        fs::write(dir.join("vendor/editor.desktop"), EDITOR).expect("failed to write entry");
+
+        let source = DirectoryEntries::new([dir.clone()]);
+        let entries = source.entries(&[], &mut Timings::new(false));
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].exec(), Some("editor"));
+
+        fs::remove_dir_all(&dir).expect("failed to remove temp dir");
+    }
+}
@@ -0,0 +1,204 @@
+use crate::xdg;
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+/// Theme used when no source names one.
+pub const DEFAULT_THEME: &str = "hicolor";
+
+/// A place the desktop environment or a toolkit stores the icon theme in.
+pub struct ThemeSource {
+    /// Command or file the theme is read from.
+    pub origin: String,
+    /// The theme named there, if any.
+    pub theme: Option<String>,
+}
+
+/// Every source of the icon theme in order of precedence: the GTK 3, GTK 4
+/// and GTK 2 settings, `xsettingsd`, KDE's `kdeglobals`, `qt6ct` and `qt5ct`,
+/// then `gsettings`.
+///
+/// `gsettings` comes last because without a running dconf it falls back to
+/// its memory backend and reports the schema default `Adwaita`, which would
+/// hide the theme the user chose in a settings file.
+pub fn theme_sources() -> Vec<ThemeSource> {
+    let mut sources = file_sources(&xdg::config_home(), &xdg::home_dir());
+    sources.push(ThemeSource {
+        origin: "gsettings org.gnome.desktop.interface icon-theme".into(),
+        theme: gsettings_theme(),
+    });
+    sources
+}
+
+/// The icon theme of the first source naming one, or [`DEFAULT_THEME`].
+pub fn detect_theme() -> String {
+    theme_sources()
+        .into_iter()
+        .find_map(|source| source.theme)
+        .unwrap_or_else(|| DEFAULT_THEME.into())
+}
+
+fn gsettings_theme() -> Option<String> {
+    let output = Command::new("gsettings")
+        .args(["get", "org.gnome.desktop.interface", "icon-theme"])
+        .output()
+        .ok()
+        .filter(|output| output.status.success())?;
+    non_empty(unquote(String::from_utf8_lossy(&output.stdout).trim()))
+}
+
+/// The configuration file sources below `config_home` and `home`.
+fn file_sources(config_home: &Path, home: &Path) -> Vec<ThemeSource> {
+    let ini = |path: PathBuf, section: &str, key: &str| {
+        let theme = fs::read_to_string(&path)
+            .ok()
+            .and_then(|contents| ini_value(&contents, Some(section), key));
+        ThemeSource {
+            origin: path.display().to_string(),
+            theme,
+        }
+    };
+    let gtkrc = home.join(".gtkrc-2.0");
+    let xsettingsd = [
+        config_home.join("xsettingsd/xsettingsd.conf"),
+        home.join(".xsettingsd"),
+    ];
+
+    let mut sources = vec![
+        ini(
+            config_home.join("gtk-3.0/settings.ini"),
+            "Settings",
+            "gtk-icon-theme-name",
+        ),
+        ini(
+            config_home.join("gtk-4.0/settings.ini"),
+            "Settings",
+            "gtk-icon-theme-name",
+        ),
+        ThemeSource {
+            theme: fs::read_to_string(&gtkrc)
+                .ok()
+                .and_then(|contents| ini_value(&contents, None, "gtk-icon-theme-name")),
+            origin: gtkrc.display().to_string(),
+        },
+    ];
+    sources.extend(xsettingsd.into_iter().map(|path| {
+        ThemeSource {
+            theme: fs::read_to_string(&path)
+                .ok()
+                .and_then(|contents| xsettingsd_value(&contents, "Net/IconThemeName")),
+            origin: path.display().to_string(),
+        }
+    }));
+    sources.extend([
+        ini(config_home.join("kdeglobals"), "Icons", "Theme"),
+        ini(
+            config_home.join("qt6ct/qt6ct.conf"),
+            "Appearance",
+            "icon_theme",
+        ),
+        ini(
+            config_home.join("qt5ct/qt5ct.conf"),
+            "Appearance",
+            "icon_theme",
+        ),
+    ]);
+    sources
+}
+
+/// Value of `key` in `section` of an INI style file; with `section` `None`
+/// only keys before the first section header are considered.
+fn ini_value(contents: &str, section: Option<&str>, key: &str) -> Option<String> {
+    let mut current = None;
+    for line in contents.lines().map(str::trim) {
+        if let Some(name) = line.strip_prefix('[').and_then(|l| l.strip_suffix(']')) {
+            current = Some(name);
+            continue;
+        }
+        if current != section {
+            continue;
+        }
+        if let Some((name, value)) = line.split_once('=')
+            && name.trim() == key
+        {
+            return non_empty(unquote(value.trim()));
+        }
+    }
+    None
+}
+
+/// Value of `key` in an `xsettingsd` configuration, e.g.
+/// `Net/IconThemeName "Papirus"`.
+fn xsettingsd_value(contents: &str, key: &str) -> Option<String> {
+    contents.lines().find_map(|line| {
+        let (name, value) = line.trim().split_once(char::is_whitespace)?;
+        (name == key).then(|| non_empty(unquote(value.trim())))?
+    })
+}
+
+fn unquote(value: &str) -> &str {
+    value.trim_matches(|c| c == '"' || c == '\'')
+}
+
+fn non_empty(value: &str) -> Option<String> {
+    (!value.is_empty()).then(|| value.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::{SystemTime, UNIX_EPOCH};
+
+    #[test]
+    fn ini_value_respects_sections() {
+        let contents =
+            "gtk-icon-theme-name = \"Top\"\n[Icons]\nTheme=breeze\n[Other]\nTheme=wrong\n";
+        assert_eq!(
+            ini_value(contents, Some("Icons"), "Theme").as_deref(),
+            Some("breeze")
+        );
+        assert_eq!(
+            ini_value(contents, None, "gtk-icon-theme-name").as_deref(),
+            Some("Top")
+        );
+        assert_eq!(ini_value(contents, Some("Missing"), "Theme"), None);
+        assert_eq!(
+            xsettingsd_value(
+                "Net/ThemeName \"Arc\"\nNet/IconThemeName \"Papirus\"\n",
+                "Net/IconThemeName"
+            )
+            .as_deref(),
+            Some("Papirus")
+        );
+    }
+
+    #[test]
+    fn file_sources_follow_precedence() {
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .expect("time went backwards")
+            .as_nanos();
+        let home = std::env::temp_dir().join(format!("box-menu-rs-test-theme-{}", timestamp));
+        let config = home.join(".config");
+        fs::create_dir_all(config.join("qt5ct")).expect("failed to create temp dir");
+        fs::write(config.join("kdeglobals"), "[Icons]\nTheme=breeze\n")
+            .expect("failed to write kdeglobals");
+        fs::write(
+            config.join("qt5ct/qt5ct.conf"),
+            "[Appearance]\nicon_theme=Papirus\n",
+        )
+        .expect("failed to write qt5ct.conf");
+        fs::write(home.join(".xsettingsd"), "Net/IconThemeName \"Adwaita\"\n")
+            .expect("failed to write xsettingsd");
+
+        let themes: Vec<_> = file_sources(&config, &home)
+            .into_iter()
+            .filter_map(|source| source.theme)
+            .collect();
+        assert_eq!(themes, ["Adwaita", "breeze", "Papirus"]);
+
+        fs::remove_dir_all(&home).expect("failed to remove temp dir");
+    }
+}
@@ -0,0 +1,48 @@
+use std::time::{Duration, Instant};
+
+/// Accumulated durations of the menu generation phases, printed to stderr
+/// with `--timings`.
+pub struct Timings {
+    enabled: bool,
+    started: Instant,
+    phases: Vec<(&'static str, Duration)>,
+}
+
+impl Timings {
+    pub fn new(enabled: bool) -> Self {
+        Self {
+            enabled,
+            started: Instant::now(),
+            phases: Vec::new(),
+        }
+    }
+
+    /// Runs `f` and adds its duration to `phase`.
+    pub fn time<R>(&mut self, phase: &'static str, f: impl FnOnce() -> R) -> R {
+        let start = Instant::now();
+        let result = f();
+        self.add(phase, start.elapsed());
+        result
+    }
+
+    pub fn add(&mut self, phase: &'static str, duration: Duration) {
+        match self.phases.iter_mut().find(|(name, _)| *name == phase) {
+            Some((_, total)) => *total += duration,
+            None => self.phases.push((phase, duration)),
+        }
+    }
+
+    pub fn report(&self) {
+        if !self.enabled {
+            return;
+        }
+        for (phase, duration) in &self.phases {
+            eprintln!("{:>8}: {:>9.3} ms", phase, duration.as_secs_f64() * 1000.0);
+        }
+        eprintln!(
+            "{:>8}: {:>9.3} ms",
+            "total",
+            self.started.elapsed().as_secs_f64() * 1000.0
+        );
+    }
+}
@@ -0,0 +1,347 @@
+use crate::cli::Reconfigure;
+use box_menu::{atomic, icon, xdg, xdg_menu};
+use inotify::{Inotify, WatchDescriptor, WatchMask};
+use std::{
+    collections::HashMap,
+    ffi::OsStr,
+    fs, io,
+    path::{Path, PathBuf},
+    process::Command,
+    thread,
+    time::Duration,
+};
+
+type Error = Box<dyn std::error::Error>;
+
+/// Directories whose changes can alter the menu: all `applications`
+/// directories with their subdirectories, the directory holding the config
+/// file, the directories holding `xdg_menu` and the menu files it merges,
+/// its `<AppDir>` and `<DirectoryDir>` directories with their
+/// subdirectories, the top-level directories of `icon_themes`, the themes
+/// they inherit from and `hicolor`, where package managers update
+/// `icon-theme.cache` after installing icons, together with the size
+/// directories their `index.theme` lists, the `hicolor` icons exported by
+/// Flatpak and the `pixmaps` directories.
+pub fn watched_dirs(
+    config_file: Option<&Path>,
+    xdg_menu: Option<&Path>,
+    icon_themes: &[String],
+) -> Vec<PathBuf> {
+    let mut dirs = Vec::new();
+    for dir in xdg::application_dirs() {
+        collect_tree(&dir, &mut dirs);
+    }
+    if let Some(parent) = config_file.and_then(Path::parent) {
+        dirs.push(parent.to_path_buf());
+    }
+    if let Some(menu_file) = xdg_menu {
+        collect_menu_dirs(menu_file, &mut dirs);
+    }
+
+    collect_theme_dirs(&xdg::icon_dirs(), icon_themes, &mut dirs);
+    for base in xdg::flatpak_icon_dirs() {
+        collect_theme_dir(&base.join("hicolor"), &mut dirs);
+    }
+    dirs.extend(xdg::pixmap_dirs());
+
+    dirs.retain(|dir| dir.is_dir());
+    dirs.sort();
+    dirs.dedup();
+    dirs
+}
+
+/// Adds the directories of the menu files `menu_file` is built from, its
+/// merge directories and its `<AppDir>` and `<DirectoryDir>` trees. If the
+/// menu cannot be read, only the directory holding it is added, so that
+/// fixing it triggers a new run.
+fn collect_menu_dirs(menu_file: &Path, dirs: &mut Vec<PathBuf>) {
+    let Ok(sources) = xdg_menu::menu_sources(menu_file) else {
+        dirs.extend(menu_file.parent().map(Path::to_path_buf));
+        return;
+    };
+    for file in &sources.files {
+        // Merge directories are listed as files; non-directories are
+        // dropped again by the caller.
+        dirs.push(file.clone());
+        dirs.extend(file.parent().map(Path::to_path_buf));
+    }
+    for dir in &sources.dirs {
+        collect_tree(dir, dirs);
+    }
+}
+
+fn collect_theme_dirs(bases: &[PathBuf], icon_themes: &[String], dirs: &mut Vec<PathBuf>) {
+    for theme in icon::lookup_order(icon_themes, bases) {
+        for base in bases {
+            collect_theme_dir(&base.join(&theme), dirs);
+        }
+    }
+}
+
+/// Adds the theme directory `dir` and the size directories it lists. Icons
+/// installed without updating `icon-theme.cache` only change the latter.
+fn collect_theme_dir(dir: &Path, dirs: &mut Vec<PathBuf>) {
+    dirs.push(dir.to_path_buf());
+    dirs.extend(icon::theme_size_dirs(dir));
+}
+
+fn collect_tree(dir: &Path, dirs: &mut Vec<PathBuf>) {
+    let Ok(read_dir) = fs::read_dir(dir) else {
+        return;
+    };
+    dirs.push(dir.to_path_buf());
+    let mut subdirs: Vec<PathBuf> = read_dir
+        .filter_map(Result::ok)
+        .map(|entry| entry.path())
+        .filter(|path| path.is_dir())
+        .collect();
+    subdirs.sort();
+    for subdir in subdirs {
+        collect_tree(&subdir, dirs);
+    }
+}
+
+/// Writes the menu returned by `generate` to `output`, then regenerates it
+/// after every burst of changes in the directories returned by `dirs`.
+/// A burst ends once nothing changed for `debounce`. Runs until an I/O
+/// error occurs; failures to regenerate the menu after the first run only
+/// print a warning and keep the previous output.
+pub fn watch(
+    output: &Path,
+    debounce: Duration,
+    reconfigure: Option<Reconfigure>,
+    mut dirs: impl FnMut() -> Vec<PathBuf>,
+    mut generate: impl FnMut() -> Result<String, Error>,
+) -> Result<(), Error> {
+    let mut first_run = true;
+    loop {
+        // Watches are recreated on every round so that new application
+        // subdirectories are picked up, and set up before generating so that
+        // no change goes unnoticed.
+        let mut inotify = Inotify::init()?;
+        let mut watched = HashMap::new();
+        for dir in dirs() {
+            let mask = WatchMask::CLOSE_WRITE
+                | WatchMask::CREATE
+                | WatchMask::DELETE
+                | WatchMask::MOVE
+                | WatchMask::DELETE_SELF
+                | WatchMask::MOVE_SELF;
+            match inotify.watches().add(&dir, mask) {
+                Ok(wd) => {
+                    watched.insert(wd, dir);
+                }
+                Err(err) => eprintln!("warning: cannot watch {}: {}", dir.display(), err),
+            }
+        }
+
+        match generate() {
+            Ok(menu) => {
+                atomic::write_file(output, menu.as_bytes())?;
+                println!("Wrote {}", output.display());
+                if let Some(window_manager) = reconfigure {
+                    send_reconfigure(window_manager);
+                }
+            }
+            Err(err) if first_run => return Err(err),
+            Err(err) => eprintln!("warning: failed to regenerate menu: {}", err),
+        }
+        first_run = false;
+        wait_for_changes(&mut inotify, &watched, output, debounce)?;
+    }
+}
+
+/// Blocks until a relevant change happened and no further change followed
+/// within `debounce`.
+fn wait_for_changes(
+    inotify: &mut Inotify,
+    watched: &HashMap<WatchDescriptor, PathBuf>,
+    output: &Path,
+    debounce: Duration,
+) -> io::Result<()> {
+    let mut buffer = [0; 4096];
+    loop {
+        let events = inotify.read_events_blocking(&mut buffer)?;
+        if events
+            .into_iter()
+            .any(|event| is_relevant(watched.get(&event.wd), event.name, output))
+        {
+            break;
+        }
+    }
+    loop {
+        thread::sleep(debounce);
+        let mut changed = false;
+        loop {
+            match inotify.read_events(&mut buffer) {
+                Ok(events) => {
+                    changed |= events
+                        .into_iter()
+                        .any(|event| is_relevant(watched.get(&event.wd), event.name, output));
+                }
+                Err(err) if err.kind() == io::ErrorKind::WouldBlock => break,
+                Err(err) => return Err(err),
+            }
+        }
+        if !changed {
+            return Ok(());
+        }
+    }
+}
+
+/// Everything except writes to the output file itself (or its temporary
+/// sibling) counts as a change.
+fn is_relevant(dir: Option<&PathBuf>, name: Option<&OsStr>, output: &Path) -> bool {
+    let (Some(dir), Some(name), Some(output_name)) = (dir, name, output.file_name()) else {
+        return true;
+    };
+    let is_output = Some(dir.as_path()) == output.parent()
+        && name
+            .as_encoded_bytes()
+            .starts_with(output_name.as_encoded_bytes());
+    !is_output
+}
+
+fn send_reconfigure(window_manager: Reconfigure) {
+    let program = match window_manager {
+        Reconfigure::Labwc => "labwc",
+        Reconfigure::Openbox => "openbox",
+    };
+    match Command::new(program).arg("--reconfigure").status() {
+        Ok(status) if status.success() => {}
+        Ok(status) => eprintln!("warning: {} --reconfigure failed: {}", program, status),
+        Err(err) => eprintln!("warning: failed to run {} --reconfigure: {}", program, err),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::{SystemTime, UNIX_EPOCH};
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .expect("time went backwards")
+            .as_nanos();
+        std::env::temp_dir().join(format!("box-menu-rs-test-{}-{}", name, timestamp))
+    }
+
+    #[test]
+    fn watched_dirs_cover_the_xdg_menu_sources() {
+        let dir = temp_dir("watch-menu");
+        let merged = dir.join("merged");
+        let apps = dir.join("apps");
+        let directories = dir.join("directories");
+        fs::create_dir_all(merged.join("applications-merged")).expect("failed to create dir");
+        fs::create_dir_all(apps.join("games")).expect("failed to create dir");
+        fs::create_dir_all(&directories).expect("failed to create dir");
+        fs::write(
+            dir.join("main.menu"),
+            format!(
+                "<Menu><Name>Applications</Name>\
+                 <AppDir>{}</AppDir><DirectoryDir>{}</DirectoryDir>\
+                 <MergeFile>merged/extra.menu</MergeFile>\
+                 <MergeDir>merged/applications-merged</MergeDir></Menu>",
+                apps.display(),
+                directories.display()
+            ),
+        )
+        .expect("failed to write menu");
+        fs::write(
+            merged.join("extra.menu"),
+            "<Menu><Name>Applications</Name></Menu>",
+        )
+        .expect("failed to write menu");
+
+        let dirs = watched_dirs(None, Some(&dir.join("main.menu")), &[]);
+        for expected in [
+            &dir,
+            &merged,
+            &merged.join("applications-merged"),
+            &apps,
+            &apps.join("games"),
+            &directories,
+        ] {
+            assert!(
+                dirs.contains(expected),
+                "{} is not watched",
+                expected.display()
+            );
+        }
+
+        fs::remove_dir_all(&dir).expect("failed to remove temp dir");
+    }
+
+    #[test]
+    fn watched_dirs_cover_an_unreadable_xdg_menu() {
+        let dir = temp_dir("watch-broken-menu");
+        fs::create_dir_all(&dir).expect("failed to create dir");
+        fs::write(dir.join("main.menu"), "<Menu>").expect("failed to write menu");
+
+        assert!(watched_dirs(None, Some(&dir.join("main.menu")), &[]).contains(&dir));
+
+        fs::remove_dir_all(&dir).expect("failed to remove temp dir");
+    }
+
+    #[test]
+    fn theme_dirs_include_listed_size_dirs_and_parents() {
+        let base = temp_dir("watch-themes");
+        let theme = base.join("Test");
+        fs::create_dir_all(theme.join("48x48/apps")).expect("failed to create dir");
+        fs::create_dir_all(base.join("Parent/scalable/apps")).expect("failed to create dir");
+        fs::write(
+            theme.join("index.theme"),
+            "[Icon Theme]\nName=Test\nInherits=Parent\nDirectories=48x48/apps\n",
+        )
+        .expect("failed to write index.theme");
+        fs::write(
+            base.join("Parent/index.theme"),
+            "[Icon Theme]\nName=Parent\nDirectories=scalable/apps\n",
+        )
+        .expect("failed to write index.theme");
+
+        let mut dirs = Vec::new();
+        collect_theme_dirs(
+            std::slice::from_ref(&base),
+            &["Test".to_string()],
+            &mut dirs,
+        );
+        assert_eq!(
+            dirs,
+            [
+                theme.clone(),
+                theme.join("48x48/apps"),
+                base.join("Parent"),
+                base.join("Parent/scalable/apps"),
+                base.join("hicolor"),
+            ]
+        );
+
+        fs::remove_dir_all(&base).expect("failed to remove temp dir");
+    }
+
+    #[test]
+    fn is_relevant_ignores_output_file_writes() {
+        let dir = PathBuf::from("/home/user/.config/labwc");
+        let output = dir.join("menu-apps.xml");
+
+        assert!(!is_relevant(
+            Some(&dir),
+            Some(OsStr::new("menu-apps.xml.tmp-42")),
+            &output
+        ));
+        assert!(!is_relevant(
+            Some(&dir),
+            Some(OsStr::new("menu-apps.xml")),
+            &output
+        ));
+        assert!(is_relevant(Some(&dir), Some(OsStr::new("rc.xml")), &output));
+        assert!(is_relevant(
+            Some(&PathBuf::from("/usr/share/applications")),
+            Some(OsStr::new("menu-apps.xml")),
+            &output
+        ));
+        assert!(is_relevant(None, None, &output));
+    }
+}
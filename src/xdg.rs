@@ -0,0 +1,102 @@
+use std::{env, path::PathBuf};
+
+pub fn home_dir() -> PathBuf {
+    env::var_os("HOME").map(PathBuf::from).unwrap_or_default()
+}
+
+fn base_dir(var: &str, fallback: &str) -> PathBuf {
+    env::var_os(var)
+        .map(PathBuf::from)
+        .filter(|path| path.is_absolute())
+        .unwrap_or_else(|| home_dir().join(fallback))
+}
+
+/// `$XDG_CONFIG_HOME`, defaulting to `~/.config`.
+pub fn config_home() -> PathBuf {
+    base_dir("XDG_CONFIG_HOME", ".config")
+}
+
+/// `$XDG_DATA_HOME`, defaulting to `~/.local/share`.
+pub fn data_home() -> PathBuf {
+    base_dir("XDG_DATA_HOME", ".local/share")
+}
+
+/// `$XDG_CACHE_HOME`, defaulting to `~/.cache`.
+pub fn cache_home() -> PathBuf {
+    base_dir("XDG_CACHE_HOME", ".cache")
+}
+
+/// `applications` directories searched for desktop entries, most important
+/// first.
+pub fn application_dirs() -> Vec<PathBuf> {
+    std::iter::once(data_home())
+        .chain(data_dirs())
+        .map(|dir| dir.join("applications"))
+        .collect()
+}
+
+/// Base directories of icon themes, most important first: `~/.icons`,
+/// then `icons` below `$XDG_DATA_HOME` and `$XDG_DATA_DIRS`.
+pub fn icon_dirs() -> Vec<PathBuf> {
+    [home_dir().join(".icons"), data_home().join("icons")]
+        .into_iter()
+        .chain(data_dirs().into_iter().map(|dir| dir.join("icons")))
+        .collect()
+}
+
+/// Directories Flatpak exports the `hicolor` icons of installed apps to,
+/// per user first: below `$XDG_DATA_HOME`, the `flatpak/exports/share`
+/// entries of `$XDG_DATA_DIRS` and, as a fallback for sessions whose
+/// `$XDG_DATA_DIRS` lacks it, the system installation.
+pub fn flatpak_icon_dirs() -> Vec<PathBuf> {
+    let mut dirs: Vec<PathBuf> = std::iter::once(data_home().join("flatpak/exports/share"))
+        .chain(
+            data_dirs()
+                .into_iter()
+                .filter(|dir| dir.ends_with("flatpak/exports/share")),
+        )
+        .map(|dir| dir.join("icons"))
+        .collect();
+    push_fallback(&mut dirs, "/var/lib/flatpak/exports/share/icons");
+    dirs
+}
+
+/// Legacy `pixmaps` directories below `$XDG_DATA_HOME` and
+/// `$XDG_DATA_DIRS`, followed by `/usr/share/pixmaps` if not among them.
+pub fn pixmap_dirs() -> Vec<PathBuf> {
+    let mut dirs: Vec<PathBuf> = std::iter::once(data_home())
+        .chain(data_dirs())
+        .map(|dir| dir.join("pixmaps"))
+        .collect();
+    push_fallback(&mut dirs, "/usr/share/pixmaps");
+    dirs
+}
+
+fn push_fallback(dirs: &mut Vec<PathBuf>, fallback: &str) {
+    let fallback = PathBuf::from(fallback);
+    if !dirs.contains(&fallback) {
+        dirs.push(fallback);
+    }
+}
+
+fn search_dirs(var: &str, fallback: &str) -> Vec<PathBuf> {
+    let value = env::var(var)
+        .ok()
+        .filter(|value| !value.trim().is_empty())
+        .unwrap_or_else(|| fallback.to_string());
+    value
+        .split(':')
+        .map(PathBuf::from)
+        .filter(|path| path.is_absolute())
+        .collect()
+}
+
+/// `$XDG_DATA_DIRS` in order of preference, without `$XDG_DATA_HOME`.
+pub fn data_dirs() -> Vec<PathBuf> {
+    search_dirs("XDG_DATA_DIRS", "/usr/local/share:/usr/share")
+}
+
+/// `$XDG_CONFIG_DIRS` in order of preference, without `$XDG_CONFIG_HOME`.
+pub fn config_dirs() -> Vec<PathBuf> {
+    search_dirs("XDG_CONFIG_DIRS", "/etc/xdg")
+}
@@ -0,0 +1,1136 @@
+use crate::{
+    builder::{Menu, MenuBuilder},
+    config::Config,
+    icon::IconResolver,
+    menu::{Entry, LayoutItem, MenuNode, Scope, make_scoped_entry},
+    parallel,
+    timings::Timings,
+    visibility::visibility_exclusion_reason,
+    xdg,
+};
+use freedesktop_desktop_entry::DesktopEntry;
+use roxmltree::{Document, Node, ParsingOptions};
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    fs,
+    path::{Path, PathBuf},
+};
+
+type Error = Box<dyn std::error::Error>;
+
+/// Matching rule of an `<Include>` or `<Exclude>` element.
+enum Rule {
+    All,
+    Filename(String),
+    Category(String),
+    And(Vec<Rule>),
+    Or(Vec<Rule>),
+    Not(Vec<Rule>),
+}
+
+impl Rule {
+    fn matches(&self, id: &str, categories: &[&str]) -> bool {
+        match self {
+            Rule::All => true,
+            Rule::Filename(filename) => filename == id,
+            Rule::Category(category) => categories.contains(&category.as_str()),
+            Rule::And(rules) => rules.iter().all(|rule| rule.matches(id, categories)),
+            Rule::Or(rules) => rules.iter().any(|rule| rule.matches(id, categories)),
+            Rule::Not(rules) => !rules.iter().any(|rule| rule.matches(id, categories)),
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum MergeType {
+    Menus,
+    Files,
+    All,
+}
+
+enum LayoutRule {
+    Filename(String),
+    Menuname(String),
+    Separator,
+    Merge(MergeType),
+}
+
+struct Layout {
+    show_empty: bool,
+    rules: Vec<LayoutRule>,
+}
+
+impl Default for Layout {
+    /// The layout the specification prescribes when none is given.
+    fn default() -> Self {
+        Self {
+            show_empty: false,
+            rules: vec![
+                LayoutRule::Merge(MergeType::Menus),
+                LayoutRule::Merge(MergeType::Files),
+            ],
+        }
+    }
+}
+
+/// A `<Menu>` element after all merge elements have been resolved.
+#[derive(Default)]
+struct MenuDef {
+    name: String,
+    app_dirs: Vec<PathBuf>,
+    directory_dirs: Vec<PathBuf>,
+    directories: Vec<String>,
+    deleted: Option<bool>,
+    only_unallocated: Option<bool>,
+    /// `<Include>` (`true`) and `<Exclude>` (`false`) rules in document order.
+    rules: Vec<(bool, Rule)>,
+    layout: Option<Layout>,
+    default_layout: Option<Layout>,
+    submenus: Vec<MenuDef>,
+}
+
+impl MenuDef {
+    /// Appends the content of a later menu with the same name.
+    fn absorb(&mut self, other: MenuDef) {
+        self.app_dirs.extend(other.app_dirs);
+        self.directory_dirs.extend(other.directory_dirs);
+        self.directories.extend(other.directories);
+        self.deleted = other.deleted.or(self.deleted);
+        self.only_unallocated = other.only_unallocated.or(self.only_unallocated);
+        self.rules.extend(other.rules);
+        self.layout = other.layout.or(self.layout.take());
+        self.default_layout = other.default_layout.or(self.default_layout.take());
+        self.submenus.extend(other.submenus);
+    }
+
+    /// Merges sibling menus sharing a name, recursively.
+    fn consolidate(&mut self) {
+        let mut merged: Vec<MenuDef> = Vec::new();
+        for submenu in self.submenus.drain(..) {
+            if let Some(existing) = merged.iter_mut().find(|m| m.name == submenu.name) {
+                existing.absorb(submenu);
+            } else {
+                merged.push(submenu);
+            }
+        }
+        for submenu in &mut merged {
+            submenu.consolidate();
+        }
+        self.submenus = merged;
+    }
+}
+
+fn element_text(node: Node) -> String {
+    node.text().unwrap_or_default().trim().to_string()
+}
+
+fn resolve_path(base_dir: &Path, text: &str) -> PathBuf {
+    let path = PathBuf::from(text);
+    if path.is_absolute() {
+        path
+    } else {
+        base_dir.join(path)
+    }
+}
+
+/// `$XDG_DATA_HOME` and `$XDG_DATA_DIRS` subdirectories, least important first
+/// so that later directories take precedence like later `<AppDir>` elements.
+fn default_data_subdirs(subdir: &str) -> Vec<PathBuf> {
+    let mut dirs: Vec<PathBuf> = xdg::data_dirs()
+        .into_iter()
+        .rev()
+        .map(|dir| dir.join(subdir))
+        .collect();
+    dirs.push(xdg::data_home().join(subdir));
+    dirs
+}
+
+/// `$XDG_CONFIG_HOME` followed by `$XDG_CONFIG_DIRS`, most important first.
+fn config_search_dirs() -> Vec<PathBuf> {
+    let mut dirs = vec![xdg::config_home()];
+    dirs.extend(xdg::config_dirs());
+    dirs
+}
+
+fn parse_rules(node: Node) -> Vec<Rule> {
+    node.children()
+        .filter(Node::is_element)
+        .filter_map(|child| match child.tag_name().name() {
+            "All" => Some(Rule::All),
+            "Filename" => Some(Rule::Filename(element_text(child))),
+            "Category" => Some(Rule::Category(element_text(child))),
+            "And" => Some(Rule::And(parse_rules(child))),
+            "Or" => Some(Rule::Or(parse_rules(child))),
+            "Not" => Some(Rule::Not(parse_rules(child))),
+            _ => None,
+        })
+        .collect()
+}
+
+fn parse_layout(node: Node) -> Layout {
+    let rules = node
+        .children()
+        .filter(Node::is_element)
+        .filter_map(|child| match child.tag_name().name() {
+            "Filename" => Some(LayoutRule::Filename(element_text(child))),
+            "Menuname" => Some(LayoutRule::Menuname(element_text(child))),
+            "Separator" => Some(LayoutRule::Separator),
+            "Merge" => match child.attribute("type") {
+                Some("menus") => Some(LayoutRule::Merge(MergeType::Menus)),
+                Some("files") => Some(LayoutRule::Merge(MergeType::Files)),
+                Some("all") => Some(LayoutRule::Merge(MergeType::All)),
+                _ => None,
+            },
+            _ => None,
+        })
+        .collect();
+    Layout {
+        show_empty: node.attribute("show_empty") == Some("true"),
+        rules,
+    }
+}
+
+/// Reads menu files, resolving `<MergeFile>`, `<MergeDir>` and
+/// `<DefaultMergeDirs>` into a single [`MenuDef`] tree.
+struct MenuParser {
+    /// `<basename>-merged` directory name for `<DefaultMergeDirs>`.
+    merged_dir_name: String,
+    /// Canonical paths of the files currently being read, to break cycles.
+    stack: Vec<PathBuf>,
+    /// Menu files and merge directories looked at, including missing ones.
+    read: Vec<PathBuf>,
+}
+
+impl MenuParser {
+    fn new(menu_file: &Path) -> Self {
+        let stem = menu_file
+            .file_stem()
+            .map(|stem| stem.to_string_lossy().into_owned())
+            .unwrap_or_else(|| "applications".into());
+        Self {
+            merged_dir_name: format!("{}-merged", stem),
+            stack: Vec::new(),
+            read: Vec::new(),
+        }
+    }
+
+    fn load_file(&mut self, path: &Path, def: &mut MenuDef, merging: bool) -> Result<(), Error> {
+        self.read.push(path.to_path_buf());
+        let canonical = path.canonicalize()?;
+        if self.stack.contains(&canonical) {
+            return Ok(());
+        }
+
+        let text = fs::read_to_string(path)?;
+        let options = ParsingOptions {
+            allow_dtd: true,
+            ..ParsingOptions::default()
+        };
+        let doc = Document::parse_with_options(&text, options)
+            .map_err(|err| format!("{}: {}", path.display(), err))?;
+        let root = doc.root_element();
+        if root.tag_name().name() != "Menu" {
+            return Err(format!("{}: root element is not <Menu>", path.display()).into());
+        }
+
+        let base_dir = canonical
+            .parent()
+            .map(Path::to_path_buf)
+            .unwrap_or_default();
+        self.stack.push(canonical.clone());
+        let result = self.parse_menu(root, &base_dir, &canonical, def, merging);
+        self.stack.pop();
+        result
+    }
+
+    /// Merges a file referenced by a merge element; missing files are skipped.
+    fn merge_file(&mut self, path: &Path, def: &mut MenuDef) -> Result<(), Error> {
+        if path.is_file() {
+            self.load_file(path, def, true)?;
+        } else {
+            self.read.push(path.to_path_buf());
+        }
+        Ok(())
+    }
+
+    fn merge_dir(&mut self, dir: &Path, def: &mut MenuDef) -> Result<(), Error> {
+        self.read.push(dir.to_path_buf());
+        let Ok(read_dir) = fs::read_dir(dir) else {
+            return Ok(());
+        };
+        let mut files: Vec<PathBuf> = read_dir
+            .filter_map(Result::ok)
+            .map(|entry| entry.path())
+            .filter(|path| path.extension().is_some_and(|ext| ext == "menu"))
+            .collect();
+        files.sort();
+        for file in files {
+            self.merge_file(&file, def)?;
+        }
+        Ok(())
+    }
+
+    /// The file with the same path relative to the next less important
+    /// config directory, for `<MergeFile type="parent">`.
+    fn parent_file(file: &Path) -> Option<PathBuf> {
+        let dirs = config_search_dirs();
+        let (position, relative) = dirs.iter().enumerate().find_map(|(i, dir)| {
+            let dir = dir.canonicalize().ok()?;
+            file.strip_prefix(&dir)
+                .ok()
+                .map(|relative| (i, relative.to_path_buf()))
+        })?;
+        dirs[position + 1..]
+            .iter()
+            .map(|dir| dir.join(&relative))
+            .find(|candidate| candidate.is_file())
+    }
+
+    fn parse_menu(
+        &mut self,
+        node: Node,
+        base_dir: &Path,
+        file: &Path,
+        def: &mut MenuDef,
+        merging: bool,
+    ) -> Result<(), Error> {
+        for child in node.children().filter(Node::is_element) {
+            match child.tag_name().name() {
+                // The root name of a merged file is ignored.
+                "Name" if !merging => def.name = element_text(child),
+                "AppDir" => def
+                    .app_dirs
+                    .push(resolve_path(base_dir, &element_text(child))),
+                "DefaultAppDirs" => def.app_dirs.extend(default_data_subdirs("applications")),
+                "DirectoryDir" => def
+                    .directory_dirs
+                    .push(resolve_path(base_dir, &element_text(child))),
+                "DefaultDirectoryDirs" => def
+                    .directory_dirs
+                    .extend(default_data_subdirs("desktop-directories")),
+                "Directory" => def.directories.push(element_text(child)),
+                "OnlyUnallocated" => def.only_unallocated = Some(true),
+                "NotOnlyUnallocated" => def.only_unallocated = Some(false),
+                "Deleted" => def.deleted = Some(true),
+                "NotDeleted" => def.deleted = Some(false),
+                "Include" => def.rules.push((true, Rule::Or(parse_rules(child)))),
+                "Exclude" => def.rules.push((false, Rule::Or(parse_rules(child)))),
+                "Layout" => def.layout = Some(parse_layout(child)),
+                "DefaultLayout" => def.default_layout = Some(parse_layout(child)),
+                "Menu" => {
+                    let mut submenu = MenuDef::default();
+                    self.parse_menu(child, base_dir, file, &mut submenu, false)?;
+                    def.submenus.push(submenu);
+                }
+                "MergeFile" => {
+                    let target = if child.attribute("type") == Some("parent") {
+                        Self::parent_file(file)
+                    } else {
+                        Some(resolve_path(base_dir, &element_text(child)))
+                    };
+                    if let Some(target) = target {
+                        self.merge_file(&target, def)?;
+                    }
+                }
+                "MergeDir" => self.merge_dir(&resolve_path(base_dir, &element_text(child)), def)?,
+                "DefaultMergeDirs" => {
+                    let merged_dir_name = self.merged_dir_name.clone();
+                    for dir in config_search_dirs().iter().rev() {
+                        self.merge_dir(&dir.join("menus").join(&merged_dir_name), def)?;
+                    }
+                }
+                // <LegacyDir>, <KDELegacyDirs> and <Move> are not supported.
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The desktop entries the menu is built from, grouped by the application
+/// directories they were read from. `<AppDir>`s outside the `applications`
+/// directories of the XDG base directories are scanned in addition.
+struct Catalog<'a> {
+    locales: &'a [String],
+    entries: &'a [DesktopEntry],
+    /// Entries read from `<AppDir>`s the given entries do not cover; their
+    /// indices follow those of `entries`.
+    scanned: Vec<DesktopEntry>,
+    /// The canonical `applications` directories the given entries are read
+    /// from.
+    system_dirs: Vec<PathBuf>,
+    by_dir: HashMap<PathBuf, Vec<(String, usize)>>,
+}
+
+impl<'a> Catalog<'a> {
+    fn new(locales: &'a [String], entries: &'a [DesktopEntry]) -> Self {
+        Self {
+            locales,
+            entries,
+            scanned: Vec::new(),
+            system_dirs: xdg::application_dirs()
+                .iter()
+                .filter_map(|dir| fs::canonicalize(dir).ok())
+                .collect(),
+            by_dir: HashMap::new(),
+        }
+    }
+
+    fn entry(&self, index: usize) -> &DesktopEntry {
+        match index.checked_sub(self.entries.len()) {
+            Some(scanned) => &self.scanned[scanned],
+            None => &self.entries[index],
+        }
+    }
+
+    fn scan(&mut self, root: &Path, dir: &Path, found: &mut Vec<(String, usize)>) {
+        let Ok(read_dir) = fs::read_dir(dir) else {
+            return;
+        };
+        let mut paths: Vec<PathBuf> = read_dir
+            .filter_map(Result::ok)
+            .map(|entry| entry.path())
+            .collect();
+        paths.sort();
+        for path in paths {
+            if path.is_dir() {
+                self.scan(root, &path, found);
+                continue;
+            }
+            let Some(id) = desktop_file_id(root, &path) else {
+                continue;
+            };
+            let Ok(entry) = DesktopEntry::from_path(path, Some(self.locales)) else {
+                continue;
+            };
+            if entry.type_().is_some_and(|type_| type_ != "Application") {
+                continue;
+            }
+            found.push((id, self.entries.len() + self.scanned.len()));
+            self.scanned.push(entry);
+        }
+    }
+
+    /// Applications below `dir` with their desktop file ids: the given
+    /// entries if `dir` is one of the directories they are read from (or
+    /// below one), otherwise the desktop files scanned from `dir`.
+    fn dir_entries(&mut self, dir: &Path) -> &[(String, usize)] {
+        if !self.by_dir.contains_key(dir) {
+            // Entry paths are canonical, so `dir` may be below a symlink,
+            // as in Nix profiles.
+            let canonical = fs::canonicalize(dir).unwrap_or_else(|_| dir.to_path_buf());
+            let covered = self
+                .system_dirs
+                .iter()
+                .any(|system| canonical.starts_with(system));
+            let found = if covered || !canonical.is_dir() {
+                self.entries
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, entry)| entry.type_().is_none_or(|type_| type_ == "Application"))
+                    .filter_map(|(index, entry)| {
+                        let id = desktop_file_id(&canonical, &entry.path)
+                            .or_else(|| desktop_file_id(dir, &entry.path))?;
+                        Some((id, index))
+                    })
+                    .collect()
+            } else {
+                let mut found = Vec::new();
+                self.scan(&canonical, &canonical, &mut found);
+                found
+            };
+            self.by_dir.insert(dir.to_path_buf(), found);
+        }
+        &self.by_dir[dir]
+    }
+
+    /// Entries visible through `app_dirs`; later directories override
+    /// earlier ones with the same desktop file id.
+    fn pool(&mut self, app_dirs: &[PathBuf]) -> BTreeMap<String, usize> {
+        let mut pool = BTreeMap::new();
+        for dir in app_dirs {
+            for (id, index) in self.dir_entries(dir) {
+                pool.insert(id.clone(), *index);
+            }
+        }
+        pool
+    }
+
+    fn select(
+        &mut self,
+        def: &MenuDef,
+        app_dirs: &[PathBuf],
+        allocated: Option<&HashSet<String>>,
+    ) -> BTreeMap<String, usize> {
+        let pool = self.pool(app_dirs);
+        let mut selected = BTreeMap::new();
+        for (include, rule) in &def.rules {
+            for (id, index) in &pool {
+                if allocated.is_some_and(|allocated| allocated.contains(id)) {
+                    continue;
+                }
+                let categories = self.entry(*index).categories().unwrap_or_default();
+                if rule.matches(id, &categories) {
+                    if *include {
+                        selected.insert(id.clone(), *index);
+                    } else {
+                        selected.remove(id);
+                    }
+                }
+            }
+        }
+        selected
+    }
+}
+
+/// Id of the desktop file `path` below the application directory `dir`;
+/// ids use '-' in place of the subdirectory separator.
+fn desktop_file_id(dir: &Path, path: &Path) -> Option<String> {
+    let relative = path.strip_prefix(dir).ok()?;
+    if relative.extension().is_none_or(|ext| ext != "desktop") {
+        return None;
+    }
+    Some(relative.to_string_lossy().replace('/', "-"))
+}
+
+/// Selected desktop entries of a [`MenuDef`] and its submenus.
+#[derive(Default)]
+struct Selection {
+    entries: BTreeMap<String, usize>,
+    submenus: Vec<Selection>,
+}
+
+/// Runs one allocation pass: the first handles menus without
+/// `<OnlyUnallocated>` and records what they claimed, the second fills the
+/// `<OnlyUnallocated>` menus from the rest.
+fn allocate(
+    def: &MenuDef,
+    parent_app_dirs: &[PathBuf],
+    catalog: &mut Catalog,
+    selection: &mut Selection,
+    allocated: &mut HashSet<String>,
+    only_unallocated_pass: bool,
+) {
+    // Deleted menus are not shown, so they must not claim entries either.
+    if def.deleted == Some(true) {
+        return;
+    }
+
+    let mut app_dirs = parent_app_dirs.to_vec();
+    app_dirs.extend(def.app_dirs.iter().cloned());
+
+    let only_unallocated = def.only_unallocated.unwrap_or(false);
+    if only_unallocated == only_unallocated_pass {
+        if only_unallocated {
+            selection.entries = catalog.select(def, &app_dirs, Some(allocated));
+        } else {
+            selection.entries = catalog.select(def, &app_dirs, None);
+            allocated.extend(selection.entries.keys().cloned());
+        }
+    }
+
+    selection
+        .submenus
+        .resize_with(def.submenus.len(), Selection::default);
+    for (submenu, sub_selection) in def.submenus.iter().zip(&mut selection.submenus) {
+        allocate(
+            submenu,
+            &app_dirs,
+            catalog,
+            sub_selection,
+            allocated,
+            only_unallocated_pass,
+        );
+    }
+}
+
+struct DirectoryInfo {
+    name: Option<String>,
+    icon: Option<String>,
+    no_display: bool,
+}
+
+/// Reads the last `<Directory>` found in the most important directory dir.
+fn directory_info(def: &MenuDef, directory_dirs: &[PathBuf], locales: &[String]) -> DirectoryInfo {
+    for directory in def.directories.iter().rev() {
+        for dir in directory_dirs.iter().rev() {
+            let path = dir.join(directory);
+            let Ok(input) = fs::read_to_string(&path) else {
+                continue;
+            };
+            // The parser derives an app id from the .desktop suffix.
+            let Ok(entry) =
+                DesktopEntry::from_str(path.with_extension("desktop"), &input, Some(locales))
+            else {
+                continue;
+            };
+            return DirectoryInfo {
+                name: entry.name(locales).map(|name| name.to_string()),
+                icon: entry.icon().map(str::to_string),
+                no_display: entry.no_display(),
+            };
+        }
+    }
+    DirectoryInfo {
+        name: None,
+        icon: None,
+        no_display: false,
+    }
+}
+
+/// Shared state while turning the selected [`MenuDef`] tree into menu nodes.
+struct TreeBuilder<'a> {
+    catalog: &'a Catalog<'a>,
+    icons: &'a dyn IconResolver,
+    config: &'a Config,
+    current_desktop: Option<&'a HashSet<String>>,
+    scope: &'a Scope,
+    excluded: &'a mut Vec<(String, String)>,
+    excluded_ids: HashSet<String>,
+    timings: &'a mut Timings,
+}
+
+impl TreeBuilder<'_> {
+    fn build(
+        &mut self,
+        def: &MenuDef,
+        selection: &Selection,
+        path: &str,
+        parent_directory_dirs: &[PathBuf],
+        parent_default_layout: Option<&Layout>,
+        is_root: bool,
+    ) -> Option<MenuNode> {
+        if def.deleted == Some(true) {
+            return None;
+        }
+
+        let mut directory_dirs = parent_directory_dirs.to_vec();
+        directory_dirs.extend(def.directory_dirs.iter().cloned());
+        let info = directory_info(def, &directory_dirs, self.catalog.locales);
+        if info.no_display && !is_root {
+            return None;
+        }
+
+        let default_layout = def.default_layout.as_ref().or(parent_default_layout);
+        let mut children = BTreeMap::new();
+        for (submenu, sub_selection) in def.submenus.iter().zip(&selection.submenus) {
+            if submenu.name.is_empty() {
+                continue;
+            }
+            let child_path = if path.is_empty() {
+                submenu.name.clone()
+            } else {
+                format!("{}/{}", path, submenu.name)
+            };
+            if let Some(child) = self.build(
+                submenu,
+                sub_selection,
+                &child_path,
+                &directory_dirs,
+                default_layout,
+                false,
+            ) {
+                children.insert(submenu.name.clone(), child);
+            }
+        }
+
+        let mut visible = Vec::new();
+        for (id, index) in &selection.entries {
+            let desktop_entry = self.catalog.entry(*index);
+            if self.config.options.visibility_filter
+                && let Some(reason) =
+                    visibility_exclusion_reason(desktop_entry, self.current_desktop)
+            {
+                if self.excluded_ids.insert(id.clone()) {
+                    let label = desktop_entry
+                        .full_name(self.catalog.locales)
+                        .unwrap_or_default()
+                        .to_string();
+                    self.excluded.push((label, reason));
+                }
+                continue;
+            }
+            visible.push((id, desktop_entry));
+        }
+        let (locales, config, icons, scope) =
+            (self.catalog.locales, self.config, self.icons, self.scope);
+        // The `category` icon fallback uses the icon the menu is shown with.
+        let category_icon = config.icon_for_menu_source(path, info.icon.as_deref());
+        let menu_entries = self.timings.time("icons", || {
+            parallel::map(&visible, |(_, desktop_entry)| {
+                make_scoped_entry(
+                    desktop_entry,
+                    locales,
+                    config,
+                    icons,
+                    scope,
+                    path,
+                    &category_icon,
+                )
+            })
+        });
+        let entries: BTreeMap<String, Entry> = visible
+            .iter()
+            .map(|(id, _)| (*id).clone())
+            .zip(menu_entries)
+            .collect();
+
+        let fallback_layout = Layout::default();
+        let layout = def
+            .layout
+            .as_ref()
+            .or(default_layout)
+            .unwrap_or(&fallback_layout);
+        let items = apply_layout(layout, &children, &entries);
+        let is_empty = !items
+            .iter()
+            .any(|item| !matches!(item, LayoutItem::Separator));
+        if is_empty && !layout.show_empty && !is_root {
+            return None;
+        }
+
+        let label = if is_root {
+            String::new()
+        } else {
+            info.name.unwrap_or_else(|| def.name.clone())
+        };
+        let mut node = MenuNode::new(label);
+        node.set_icon(info.icon);
+        for (name, child) in children {
+            node.add_child(name, child);
+        }
+        node.set_layout(items);
+        Some(node)
+    }
+}
+
+fn apply_layout(
+    layout: &Layout,
+    children: &BTreeMap<String, MenuNode>,
+    entries: &BTreeMap<String, Entry>,
+) -> Vec<LayoutItem> {
+    let mentioned_menus: HashSet<&str> = layout
+        .rules
+        .iter()
+        .filter_map(|rule| match rule {
+            LayoutRule::Menuname(name) => Some(name.as_str()),
+            _ => None,
+        })
+        .collect();
+    let mentioned_files: HashSet<&str> = layout
+        .rules
+        .iter()
+        .filter_map(|rule| match rule {
+            LayoutRule::Filename(id) => Some(id.as_str()),
+            _ => None,
+        })
+        .collect();
+
+    let mut items = Vec::new();
+    for rule in &layout.rules {
+        match rule {
+            LayoutRule::Filename(id) => {
+                if let Some(entry) = entries.get(id) {
+                    items.push(LayoutItem::Entry(entry.clone()));
+                }
+            }
+            LayoutRule::Menuname(name) => {
+                if children.contains_key(name) {
+                    items.push(LayoutItem::Menu(name.clone()));
+                }
+            }
+            LayoutRule::Separator => items.push(LayoutItem::Separator),
+            LayoutRule::Merge(merge_type) => {
+                let mut merged: Vec<(String, LayoutItem)> = Vec::new();
+                if *merge_type != MergeType::Files {
+                    merged.extend(
+                        children
+                            .iter()
+                            .filter(|(name, _)| !mentioned_menus.contains(name.as_str()))
+                            .map(|(name, child)| {
+                                (child.label().to_lowercase(), LayoutItem::Menu(name.clone()))
+                            }),
+                    );
+                }
+                if *merge_type != MergeType::Menus {
+                    merged.extend(
+                        entries
+                            .iter()
+                            .filter(|(id, _)| !mentioned_files.contains(id.as_str()))
+                            .map(|(_, entry)| {
+                                (entry.label.to_lowercase(), LayoutItem::Entry(entry.clone()))
+                            }),
+                    );
+                }
+                merged.sort_by(|a, b| a.0.cmp(&b.0));
+                items.extend(merged.into_iter().map(|(_, item)| item));
+            }
+        }
+    }
+
+    // Drop leading, trailing and repeated separators.
+    let mut collapsed: Vec<LayoutItem> = Vec::with_capacity(items.len());
+    for item in items {
+        let is_separator = matches!(item, LayoutItem::Separator);
+        if is_separator
+            && collapsed
+                .last()
+                .is_none_or(|last| matches!(last, LayoutItem::Separator))
+        {
+            continue;
+        }
+        collapsed.push(item);
+    }
+    if collapsed
+        .last()
+        .is_some_and(|last| matches!(last, LayoutItem::Separator))
+    {
+        collapsed.pop();
+    }
+    collapsed
+}
+
+/// Reads `menu_file` with everything it merges, returning the resulting
+/// menu and the files and directories looked at.
+fn parse(menu_file: &Path) -> Result<(MenuDef, Vec<PathBuf>), Error> {
+    let mut parser = MenuParser::new(menu_file);
+    let mut root = MenuDef::default();
+    parser.load_file(menu_file, &mut root, false)?;
+    root.consolidate();
+    Ok((root, parser.read))
+}
+
+/// Files and directories an XDG menu is built from besides the desktop
+/// files, for the menu cache.
+#[derive(Debug, Default)]
+pub struct MenuSources {
+    /// Menu files, merge directories and `.directory` files, including
+    /// missing ones that would be used once created.
+    pub files: Vec<PathBuf>,
+    /// `<AppDir>` and `<DirectoryDir>` directories.
+    pub dirs: Vec<PathBuf>,
+}
+
+/// Everything building the menu of `menu_file` reads except the desktop
+/// files.
+pub fn menu_sources(menu_file: &Path) -> Result<MenuSources, Error> {
+    let (root, read) = parse(menu_file)?;
+    let mut sources = MenuSources {
+        files: read,
+        dirs: Vec::new(),
+    };
+    collect_sources(&root, &[], &mut sources);
+    sources.files.sort();
+    sources.files.dedup();
+    sources.dirs.sort();
+    sources.dirs.dedup();
+    Ok(sources)
+}
+
+fn collect_sources(def: &MenuDef, parent_directory_dirs: &[PathBuf], sources: &mut MenuSources) {
+    let mut directory_dirs = parent_directory_dirs.to_vec();
+    directory_dirs.extend(def.directory_dirs.iter().cloned());
+    sources.dirs.extend(def.app_dirs.iter().cloned());
+    sources.dirs.extend(def.directory_dirs.iter().cloned());
+    for directory in &def.directories {
+        sources
+            .files
+            .extend(directory_dirs.iter().map(|dir| dir.join(directory)));
+    }
+    for submenu in &def.submenus {
+        collect_sources(submenu, &directory_dirs, sources);
+    }
+}
+
+/// Builds the menu tree from an XDG `applications.menu` file instead of
+/// `category_map`, together with the entries dropped by visibility
+/// filtering. `<AppDir>` elements select among `entries` by the directory
+/// their file is in, or are scanned if `entries` are not read from them. Icons are only resolved for entries inside the scope of
+/// `menu_builder`, using `icons`.
+pub(crate) fn load_tree(
+    menu_file: &Path,
+    entries: &[DesktopEntry],
+    menu_builder: &MenuBuilder,
+    icons: &dyn IconResolver,
+    timings: &mut Timings,
+) -> Result<Menu, Error> {
+    let (root, _) = parse(menu_file)?;
+
+    let mut catalog = Catalog::new(menu_builder.locales, entries);
+    let mut selection = Selection::default();
+    timings.time("filter", || {
+        let mut allocated = HashSet::new();
+        allocate(
+            &root,
+            &[],
+            &mut catalog,
+            &mut selection,
+            &mut allocated,
+            false,
+        );
+        allocate(
+            &root,
+            &[],
+            &mut catalog,
+            &mut selection,
+            &mut allocated,
+            true,
+        );
+    });
+
+    let mut excluded = Vec::new();
+    let mut builder = TreeBuilder {
+        catalog: &catalog,
+        icons,
+        config: menu_builder.config,
+        current_desktop: menu_builder.current_desktop,
+        scope: &menu_builder.scope,
+        excluded: &mut excluded,
+        excluded_ids: HashSet::new(),
+        timings,
+    };
+    let root = builder
+        .build(&root, &selection, "", &[], None, true)
+        .unwrap_or_else(|| MenuNode::new(String::new()));
+    Ok(Menu { root, excluded })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        icon::NoIcons,
+        menu::MenuItem,
+        source::{DirectoryEntries, EntrySource, MemoryEntries},
+    };
+    use std::time::{SystemTime, UNIX_EPOCH};
+
+    const MENU: &str = r#"<!DOCTYPE Menu PUBLIC "-//freedesktop//DTD Menu 1.0//EN"
+ "http://www.freedesktop.org/standards/menu-spec/1.0/menu.dtd">
+<Menu>
+  <Name>Applications</Name>
+  <AppDir>apps</AppDir>
+  <DirectoryDir>dirs</DirectoryDir>
+  <Menu>
+    <Name>Graphics</Name>
+    <Directory>graphics.directory</Directory>
+    <Include>
+      <And>
+        <Category>Graphics</Category>
+        <Not><Category>Office</Category></Not>
+      </And>
+    </Include>
+  </Menu>
+  <Menu>
+    <Name>System</Name>
+    <Include><Filename>kde-konsole.desktop</Filename></Include>
+  </Menu>
+  <Menu>
+    <Name>Other</Name>
+    <OnlyUnallocated/>
+    <Include><All/></Include>
+  </Menu>
+  <Menu>
+    <Name>Empty</Name>
+    <Include><Category>Nothing</Category></Include>
+  </Menu>
+  <Menu>
+    <Name>Gone</Name>
+    <Deleted/>
+    <Include><All/></Include>
+  </Menu>
+  <MergeFile>extra.menu</MergeFile>
+  <Layout>
+    <Menuname>System</Menuname>
+    <Separator/>
+    <Merge type="menus"/>
+    <Separator/>
+  </Layout>
+</Menu>
+"#;
+
+    const EXTRA_MENU: &str = r#"<Menu>
+  <Name>Ignored</Name>
+  <Menu>
+    <Name>Graphics</Name>
+    <Include><Filename>inkscape.desktop</Filename></Include>
+  </Menu>
+</Menu>
+"#;
+
+    fn write_desktop(dir: &Path, file: &str, name: &str, extra: &str) {
+        fs::write(
+            dir.join(file),
+            format!(
+                "[Desktop Entry]\nType=Application\nName={}\nExec={}\n{}",
+                name,
+                name.to_lowercase(),
+                extra
+            ),
+        )
+        .expect("failed to write desktop file");
+    }
+
+    fn fixture() -> PathBuf {
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .expect("time went backwards")
+            .as_nanos();
+        let root = std::env::temp_dir().join(format!("box-menu-rs-test-xdg-menu-{}", timestamp));
+        let apps = root.join("apps");
+        fs::create_dir_all(apps.join("kde")).expect("failed to create app dir");
+        fs::create_dir_all(root.join("dirs")).expect("failed to create directory dir");
+
+        write_desktop(&apps, "gimp.desktop", "GIMP", "Categories=Graphics;\n");
+        write_desktop(
+            &apps,
+            "inkscape.desktop",
+            "Inkscape",
+            "Categories=Graphics;Office;\n",
+        );
+        write_desktop(
+            &apps,
+            "hidden.desktop",
+            "Hidden",
+            "Categories=Graphics;\nNoDisplay=true\n",
+        );
+        write_desktop(&apps, "misc.desktop", "Misc", "Categories=Utility;\n");
+        write_desktop(
+            &apps.join("kde"),
+            "konsole.desktop",
+            "Konsole",
+            "Categories=System;\n",
+        );
+        fs::write(
+            root.join("dirs").join("graphics.directory"),
+            "[Desktop Entry]\nType=Directory\nName=Graphics Apps\nIcon=applications-graphics\n",
+        )
+        .expect("failed to write directory file");
+        fs::write(root.join("applications.menu"), MENU).expect("failed to write menu");
+        fs::write(root.join("extra.menu"), EXTRA_MENU).expect("failed to write menu");
+        root
+    }
+
+    fn describe(node: &MenuNode, path: &str, lines: &mut Vec<String>) {
+        for item in node.items(path) {
+            match item {
+                MenuItem::Menu(child_path, child) => {
+                    lines.push(format!("menu {} ({})", child_path, child.label()));
+                    describe(child, &child_path, lines);
+                }
+                MenuItem::Entry(entry) => lines.push(format!("entry {}", entry.label)),
+                MenuItem::Separator => lines.push("separator".into()),
+            }
+        }
+    }
+
+    #[test]
+    fn load_tree_applies_rules_allocation_and_layout() {
+        let root_dir = fixture();
+        let entries =
+            DirectoryEntries::new([root_dir.join("apps")]).entries(&[], &mut Timings::new(false));
+        let mut config = Config::default();
+        config.options.xdg_menu = Some(root_dir.join("applications.menu"));
+        let Menu { root, excluded } = MenuBuilder::new(&config)
+            .icons(&NoIcons)
+            .build(&entries)
+            .expect("failed to load menu");
+
+        let mut lines = Vec::new();
+        describe(&root, "", &mut lines);
+        assert_eq!(
+            lines,
+            vec![
+                "menu System (System)",
+                "entry Konsole",
+                "separator",
+                "menu Graphics (Graphics Apps)",
+                "entry GIMP",
+                "entry Inkscape",
+                "menu Other (Other)",
+                "entry Misc",
+            ]
+        );
+        assert_eq!(
+            excluded,
+            vec![("Hidden".to_string(), "NoDisplay=true".to_string())]
+        );
+
+        let graphics = root
+            .children("")
+            .find(|(path, _)| path == "Graphics")
+            .map(|(_, node)| node)
+            .expect("missing Graphics menu");
+        assert_eq!(graphics.icon(), Some("applications-graphics"));
+
+        fs::remove_dir_all(&root_dir).expect("failed to remove fixture");
+    }
+
+    #[test]
+    fn app_dirs_outside_the_data_dirs_are_scanned() {
+        let root_dir = fixture();
+        let mut config = Config::default();
+        config.options.xdg_menu = Some(root_dir.join("applications.menu"));
+        let menu = MenuBuilder::new(&config)
+            .icons(&NoIcons)
+            .build(&[])
+            .expect("failed to load menu");
+
+        let mut lines = Vec::new();
+        describe(&menu.root, "", &mut lines);
+        assert!(lines.contains(&"entry GIMP".to_string()), "{:?}", lines);
+        assert!(lines.contains(&"entry Konsole".to_string()), "{:?}", lines);
+
+        fs::remove_dir_all(&root_dir).expect("failed to remove fixture");
+    }
+
+    #[test]
+    fn entries_are_taken_from_the_given_source() {
+        let root_dir = fixture();
+        let menu_file = root_dir.join("memory.menu");
+        fs::write(
+            &menu_file,
+            "<Menu><Name>Applications</Name><AppDir>/memory/apps</AppDir>\
+             <Menu><Name>Tools</Name><Include><Category>Utility</Category></Include></Menu>\
+             </Menu>",
+        )
+        .expect("failed to write menu");
+        let entries = MemoryEntries::new()
+            .with(
+                "/memory/apps/vendor/editor.desktop",
+                "[Desktop Entry]\nType=Application\nName=Editor\nExec=editor\nCategories=Utility;\n",
+            )
+            .with(
+                "/elsewhere/misc.desktop",
+                "[Desktop Entry]\nType=Application\nName=Misc\nExec=misc\nCategories=Utility;\n",
+            )
+            .entries(&[], &mut Timings::new(false));
+        let mut config = Config::default();
+        config.options.xdg_menu = Some(menu_file);
+        let menu = MenuBuilder::new(&config)
+            .icons(&NoIcons)
+            .build(&entries)
+            .expect("failed to load menu");
+
+        let mut lines = Vec::new();
+        describe(&menu.root, "", &mut lines);
+        assert_eq!(lines, vec!["menu Tools (Tools)", "entry Editor"]);
+
+        fs::remove_dir_all(&root_dir).expect("failed to remove fixture");
+    }
+
+    #[test]
+    fn rules_combine_logically() {
+        let rule = Rule::Or(vec![
+            Rule::And(vec![
+                Rule::Category("Graphics".into()),
+                Rule::Not(vec![Rule::Category("Office".into())]),
+            ]),
+            Rule::Filename("special.desktop".into()),
+        ]);
+        assert!(rule.matches("gimp.desktop", &["Graphics"]));
+        assert!(!rule.matches("inkscape.desktop", &["Graphics", "Office"]));
+        assert!(rule.matches("special.desktop", &[]));
+        assert!(Rule::All.matches("any.desktop", &[]));
+    }
+}
@@ -0,0 +1,627 @@
+//! Runs the binary against the fake XDG tree in `tests/fixtures/xdg` and
+//! compares stdout with the snapshots in `tests/snapshots`.
+//!
+//! Set `UPDATE_SNAPSHOTS=1` to rewrite the snapshots after an intended
+//! output change, then review the diff.
+
+use std::{
+    env, fs,
+    path::{Path, PathBuf},
+    process::Command,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+fn fixture_dir() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/xdg")
+}
+
+/// A temporary `HOME` so that nothing of the real user is read or written.
+struct TestHome {
+    path: PathBuf,
+    /// `XDG_DATA_DIRS` in place of the fixture `share` directory.
+    data_dirs: Option<PathBuf>,
+}
+
+impl TestHome {
+    fn new(name: &str) -> Self {
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .expect("time went backwards")
+            .as_nanos();
+        let path = env::temp_dir().join(format!("box-menu-rs-test-cli-{}-{}", name, timestamp));
+        fs::create_dir_all(&path).expect("failed to create temp home");
+        Self {
+            path,
+            data_dirs: None,
+        }
+    }
+
+    fn with_data_dirs(mut self, data_dirs: PathBuf) -> Self {
+        self.data_dirs = Some(data_dirs);
+        self
+    }
+
+    /// Puts an executable `name` printing `stdout` on the `PATH` of the runs.
+    fn fake_command(&self, name: &str, stdout: &str) {
+        use std::os::unix::fs::PermissionsExt;
+
+        let path = self.path.join(name);
+        fs::write(&path, format!("#!/bin/sh\necho \"{}\"\n", stdout))
+            .expect("failed to write fake command");
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o755))
+            .expect("failed to make fake command executable");
+    }
+
+    /// Runs `box-menu-rs` with `args` in the fixture environment and returns
+    /// its stdout with machine specific paths replaced by placeholders.
+    fn run(&self, args: &[&str]) -> String {
+        let fixture = fixture_dir();
+        let binary = env!("CARGO_BIN_EXE_box-menu-rs");
+        let output = Command::new(binary)
+            .args(args)
+            // Paths are never resolved against the working directory.
+            .current_dir(&self.path)
+            .env_clear()
+            .env("HOME", &self.path)
+            // No `gsettings` or other helpers of the host are found.
+            .env("PATH", &self.path)
+            .env("XDG_DATA_HOME", self.path.join(".local/share"))
+            .env(
+                "XDG_DATA_DIRS",
+                self.data_dirs
+                    .clone()
+                    .unwrap_or_else(|| fixture.join("share")),
+            )
+            .env("XDG_CONFIG_HOME", fixture.join("config"))
+            .env("XDG_CONFIG_DIRS", fixture.join("config"))
+            .env("XDG_CACHE_HOME", self.path.join(".cache"))
+            .env("XDG_CURRENT_DESKTOP", "LXQt")
+            .env("LANG", "de_DE.UTF-8")
+            .output()
+            .expect("failed to run box-menu-rs");
+        assert!(
+            output.status.success(),
+            "box-menu-rs {:?} failed: {}",
+            args,
+            String::from_utf8_lossy(&output.stderr)
+        );
+        String::from_utf8(output.stdout)
+            .expect("output is not UTF-8")
+            .replace(binary, "box-menu-rs")
+            .replace(&fixture.display().to_string(), "$FIXTURE")
+            .replace(&self.path.display().to_string(), "$HOME")
+    }
+}
+
+impl Drop for TestHome {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.path);
+    }
+}
+
+fn assert_snapshot(name: &str, actual: &str) {
+    let path = Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/snapshots")
+        .join(format!("{}.txt", name));
+    if env::var_os("UPDATE_SNAPSHOTS").is_some() {
+        fs::create_dir_all(path.parent().unwrap()).expect("failed to create snapshot dir");
+        fs::write(&path, actual).expect("failed to write snapshot");
+        return;
+    }
+    let expected = fs::read_to_string(&path).unwrap_or_else(|_| {
+        panic!(
+            "missing snapshot {}, run with UPDATE_SNAPSHOTS=1 to create it",
+            path.display()
+        )
+    });
+    assert_eq!(
+        actual,
+        expected,
+        "output differs from snapshot {}",
+        path.display()
+    );
+}
+
+fn snapshot(name: &str, args: &[&str]) {
+    let home = TestHome::new(name);
+    assert_snapshot(name, &home.run(args));
+}
+
+#[test]
+fn menu_openbox() {
+    snapshot("menu_openbox", &["--no-cache"]);
+}
+
+#[test]
+fn menu_category_priority() {
+    let config = fixture_dir().join("config/priority.yml");
+    snapshot(
+        "menu_category_priority",
+        &["--no-cache", "--config-file", config.to_str().unwrap()],
+    );
+}
+
+#[test]
+fn menu_fluxbox() {
+    snapshot("menu_fluxbox", &["--no-cache", "--format", "fluxbox"]);
+}
+
+#[test]
+fn menu_nested_submenu() {
+    snapshot("menu_nested_submenu", &["--no-cache", "--submenu", "Work"]);
+}
+
+#[test]
+fn menu_lazy() {
+    snapshot("menu_lazy", &["--no-cache", "--lazy"]);
+}
+
+#[test]
+fn menu_lazy_stubs_keep_config_file_but_not_rebuild_cache() {
+    let home = TestHome::new("menu_lazy_stubs_keep_config_file_but_not_rebuild_cache");
+    fs::copy(
+        fixture_dir().join("config/box-menu-rs/config.yml"),
+        home.path.join("config.yml"),
+    )
+    .expect("failed to copy config");
+    // Relative to the working directory, the temporary home.
+    let output = home.run(&["--rebuild-cache", "--config-file", "config.yml", "--lazy"]);
+    assert!(
+        output.contains(
+            "execute=\"box-menu-rs --config-file $HOME/config.yml --lazy --submenu Games\""
+        ),
+        "{}",
+        output
+    );
+}
+
+#[test]
+fn list_all() {
+    snapshot("list_all", &["--no-cache", "--list", "all"]);
+}
+
+#[test]
+fn list_missing_icons() {
+    snapshot(
+        "list_missing_icons",
+        &["--no-cache", "--list", "missing-icons"],
+    );
+}
+
+#[test]
+fn list_excluded() {
+    snapshot("list_excluded", &["--no-cache", "--list", "excluded"]);
+}
+
+#[test]
+fn list_program() {
+    snapshot(
+        "list_program",
+        &["--no-cache", "--list", "program", "Editor"],
+    );
+}
+
+#[test]
+fn menu_icon_fallback() {
+    let config = fixture_dir().join("config/fallback.yml");
+    snapshot(
+        "menu_icon_fallback",
+        &["--no-cache", "--config-file", config.to_str().unwrap()],
+    );
+}
+
+#[test]
+fn list_program_icon_fallback() {
+    let config = fixture_dir().join("config/fallback.yml");
+    snapshot(
+        "list_program_icon_fallback",
+        &[
+            "--no-cache",
+            "--config-file",
+            config.to_str().unwrap(),
+            "--list",
+            "program",
+            "Malen",
+        ],
+    );
+}
+
+#[test]
+fn menu_category_fallback_per_category() {
+    let config = fixture_dir().join("config/category-fallback.yml");
+    snapshot(
+        "menu_category_fallback_per_category",
+        &["--no-cache", "--config-file", config.to_str().unwrap()],
+    );
+}
+
+#[test]
+fn list_program_category_fallback_per_category() {
+    let config = fixture_dir().join("config/category-fallback.yml");
+    snapshot(
+        "list_program_category_fallback_per_category",
+        &[
+            "--no-cache",
+            "--config-file",
+            config.to_str().unwrap(),
+            "--list",
+            "program",
+            "Editor",
+        ],
+    );
+}
+
+#[test]
+fn menu_hidpi_svg() {
+    let config = fixture_dir().join("config/hidpi.yml");
+    snapshot(
+        "menu_hidpi_svg",
+        &["--no-cache", "--config-file", config.to_str().unwrap()],
+    );
+}
+
+#[test]
+fn list_program_hidpi_svg() {
+    let config = fixture_dir().join("config/hidpi.yml");
+    snapshot(
+        "list_program_hidpi_svg",
+        &[
+            "--no-cache",
+            "--config-file",
+            config.to_str().unwrap(),
+            "--list",
+            "program",
+            "Editor",
+        ],
+    );
+}
+
+#[test]
+fn list_theme_from_config() {
+    snapshot("list_theme_from_config", &["--no-cache", "--list", "theme"]);
+}
+
+#[test]
+fn list_theme_detected() {
+    let config = fixture_dir().join("config/notheme.yml");
+    snapshot(
+        "list_theme_detected",
+        &[
+            "--no-cache",
+            "--config-file",
+            config.to_str().unwrap(),
+            "--list",
+            "theme",
+        ],
+    );
+}
+
+#[test]
+fn list_theme_settings_file_before_gsettings() {
+    let home = TestHome::new("list_theme_settings_file_before_gsettings");
+    // What `gsettings` prints on its memory backend without a dconf session.
+    home.fake_command("gsettings", "'Adwaita'");
+    let config = fixture_dir().join("config/notheme.yml");
+    assert_snapshot(
+        "list_theme_settings_file_before_gsettings",
+        &home.run(&[
+            "--no-cache",
+            "--config-file",
+            config.to_str().unwrap(),
+            "--list",
+            "theme",
+        ]),
+    );
+}
+
+#[test]
+fn menu_detected_theme() {
+    let config = fixture_dir().join("config/notheme.yml");
+    snapshot(
+        "menu_detected_theme",
+        &["--no-cache", "--config-file", config.to_str().unwrap()],
+    );
+}
+
+#[test]
+fn menu_icon_themes() {
+    let config = fixture_dir().join("config/themes.yml");
+    snapshot(
+        "menu_icon_themes",
+        &["--no-cache", "--config-file", config.to_str().unwrap()],
+    );
+}
+
+#[test]
+fn list_theme_icon_themes() {
+    let config = fixture_dir().join("config/themes.yml");
+    snapshot(
+        "list_theme_icon_themes",
+        &[
+            "--no-cache",
+            "--config-file",
+            config.to_str().unwrap(),
+            "--list",
+            "theme",
+        ],
+    );
+}
+
+#[test]
+fn menu_category_icons_only() {
+    let config = fixture_dir().join("config/category-icons.yml");
+    snapshot(
+        "menu_category_icons_only",
+        &["--no-cache", "--config-file", config.to_str().unwrap()],
+    );
+}
+
+#[test]
+fn menu_without_icons() {
+    let config = fixture_dir().join("config/no-icons.yml");
+    snapshot(
+        "menu_without_icons",
+        &["--no-cache", "--config-file", config.to_str().unwrap()],
+    );
+}
+
+#[test]
+fn list_missing_icons_without_icons() {
+    let config = fixture_dir().join("config/no-icons.yml");
+    snapshot(
+        "list_missing_icons_without_icons",
+        &[
+            "--no-cache",
+            "--config-file",
+            config.to_str().unwrap(),
+            "--list",
+            "missing-icons",
+        ],
+    );
+}
+
+#[test]
+fn list_icons() {
+    snapshot("list_icons", &["--no-cache", "--list", "icons"]);
+}
+
+#[test]
+fn list_icons_fallback() {
+    let config = fixture_dir().join("config/fallback.yml");
+    snapshot(
+        "list_icons_fallback",
+        &[
+            "--no-cache",
+            "--config-file",
+            config.to_str().unwrap(),
+            "--list",
+            "icons",
+        ],
+    );
+}
+
+#[test]
+fn list_icons_xdg_menu() {
+    let home = TestHome::new("list_icons_xdg_menu");
+    let config = home.path.join("config.yml");
+    fs::write(
+        &config,
+        format!(
+            "category_map: {{}}\noptions:\n  icon_theme: FixtureTheme\n  xdg_menu: {}\n",
+            fixture_dir().join("config/menus/fixture.menu").display()
+        ),
+    )
+    .expect("failed to write config");
+    assert_snapshot(
+        "list_icons_xdg_menu",
+        &home.run(&[
+            "--no-cache",
+            "--config-file",
+            config.to_str().unwrap(),
+            "--list",
+            "icons",
+        ]),
+    );
+}
+
+#[test]
+fn list_icons_xdg_menu_matches_entries_by_desktop_file_id() {
+    let home = TestHome::new("list_icons_xdg_menu_matches_entries_by_desktop_file_id");
+    let applications = home.path.join(".local/share/applications");
+    fs::create_dir_all(&applications).expect("failed to create applications dir");
+    for (file, icon) in [
+        ("dup-a.desktop", "fixture-game"),
+        ("dup-b.desktop", "fixture-terminal"),
+        ("lost.desktop", "lost-icon"),
+    ] {
+        let name = if file == "lost.desktop" {
+            "Lost"
+        } else {
+            "Twin"
+        };
+        fs::write(
+            applications.join(file),
+            format!(
+                "[Desktop Entry]\nType=Application\nName={}\nExec=twin\nIcon={}\nCategories=Game;\n",
+                name, icon
+            ),
+        )
+        .expect("failed to write desktop file");
+    }
+    let config = home.path.join("config.yml");
+    fs::write(
+        &config,
+        format!(
+            "category_map: {{}}\noptions:\n  icon_theme: FixtureTheme\n  icon_fallback: [icon, category]\n  xdg_menu: {}\n",
+            fixture_dir().join("config/menus/fixture.menu").display()
+        ),
+    )
+    .expect("failed to write config");
+    let args = ["--no-cache", "--config-file", config.to_str().unwrap()];
+
+    // The `category` step uses the icon of the `.directory` file, as in the
+    // menu itself.
+    let menu = home.run(&args);
+    assert!(
+        menu.contains(
+            "<item label=\"Lost\" icon=\"$FIXTURE/share/icons/hicolor/48x48/apps/fixture-game.png\">"
+        ),
+        "{}",
+        menu
+    );
+    assert_snapshot(
+        "list_icons_xdg_menu_matches_entries_by_desktop_file_id",
+        &home.run(&[&args[..], &["--list", "icons"]].concat()),
+    );
+}
+
+#[test]
+fn menu_xdg_menu_through_symlinked_data_dir() {
+    let home = TestHome::new("menu_xdg_menu_through_symlinked_data_dir");
+    let config = home.path.join("config.yml");
+    fs::write(
+        &config,
+        format!(
+            "category_map: {{}}\noptions:\n  icon_theme: FixtureTheme\n  xdg_menu: {}\n",
+            fixture_dir().join("config/menus/fixture.menu").display()
+        ),
+    )
+    .expect("failed to write config");
+    let args = ["--no-cache", "--config-file", config.to_str().unwrap()];
+    let direct = home.run(&args);
+    assert!(
+        direct.contains("<command>fixture-game</command>"),
+        "{}",
+        direct
+    );
+
+    // Like a Nix profile, where the data directory is a symlink.
+    let link = home.path.join("profile-share");
+    std::os::unix::fs::symlink(fixture_dir().join("share"), &link)
+        .expect("failed to create symlink");
+    let home = home.with_data_dirs(link);
+    let linked = home
+        .run(&args)
+        .replace("$HOME/profile-share", "$FIXTURE/share");
+    assert_eq!(linked, direct);
+}
+
+#[test]
+fn menu_icon_overrides() {
+    let config = fixture_dir().join("config/overrides.yml");
+    snapshot(
+        "menu_icon_overrides",
+        &["--no-cache", "--config-file", config.to_str().unwrap()],
+    );
+}
+
+#[test]
+fn list_program_icon_override() {
+    let config = fixture_dir().join("config/overrides.yml");
+    snapshot(
+        "list_program_icon_override",
+        &[
+            "--no-cache",
+            "--config-file",
+            config.to_str().unwrap(),
+            "--list",
+            "program",
+            "Malen",
+        ],
+    );
+}
+
+#[test]
+fn menu_validated_icons() {
+    let config = fixture_dir().join("config/validate.yml");
+    snapshot(
+        "menu_validated_icons",
+        &["--no-cache", "--config-file", config.to_str().unwrap()],
+    );
+}
+
+#[test]
+fn menu_validated_icons_fall_through_to_next_theme() {
+    let config = fixture_dir().join("config/validate-themes.yml");
+    snapshot(
+        "menu_validated_icons_fall_through_to_next_theme",
+        &["--no-cache", "--config-file", config.to_str().unwrap()],
+    );
+}
+
+#[test]
+fn list_icons_validated() {
+    let config = fixture_dir().join("config/validate.yml");
+    snapshot(
+        "list_icons_validated",
+        &[
+            "--no-cache",
+            "--config-file",
+            config.to_str().unwrap(),
+            "--list",
+            "icons",
+        ],
+    );
+}
+
+#[test]
+fn list_program_validated_icons() {
+    let config = fixture_dir().join("config/validate.yml");
+    snapshot(
+        "list_program_validated_icons",
+        &[
+            "--no-cache",
+            "--config-file",
+            config.to_str().unwrap(),
+            "--list",
+            "program",
+            "Arcade",
+        ],
+    );
+}
+
+#[test]
+fn cached_menu_matches_generated_menu() {
+    let home = TestHome::new("cache");
+    let generated = home.run(&[]);
+    assert!(home.path.join(".cache/box-menu-rs").is_dir());
+    assert_eq!(home.run(&[]), generated);
+    assert_eq!(home.run(&["--no-cache"]), generated);
+}
+
+#[test]
+fn cached_menu_notices_changed_merged_menu_file() {
+    let home = TestHome::new("cache-xdg-menu");
+    let menus = home.path.join("menus");
+    fs::create_dir_all(&menus).expect("failed to create menu dir");
+    let merged_menu = |category: &str| {
+        format!(
+            "<Menu><Menu><Name>Picked</Name><Include><Category>{}</Category></Include></Menu></Menu>",
+            category
+        )
+    };
+    fs::write(
+        menus.join("box-menu.menu"),
+        "<Menu><Name>Applications</Name><DefaultAppDirs/><MergeFile>merged.menu</MergeFile></Menu>",
+    )
+    .expect("failed to write menu");
+    fs::write(menus.join("merged.menu"), merged_menu("Game")).expect("failed to write menu");
+    let config = home.path.join("config.yml");
+    fs::write(
+        &config,
+        format!(
+            "category_map: {{}}\noptions:\n  xdg_menu: {}\n",
+            menus.join("box-menu.menu").display()
+        ),
+    )
+    .expect("failed to write config");
+    let args = ["--config-file", config.to_str().unwrap()];
+
+    let games = home.run(&args);
+    assert!(games.contains("fixture-game"));
+    fs::write(menus.join("merged.menu"), merged_menu("Graphics")).expect("failed to write menu");
+    let graphics = home.run(&args);
+    assert!(graphics.contains("fixture-paint"));
+    assert!(!graphics.contains("fixture-game"));
+    assert_eq!(graphics, home.run(&[&["--no-cache"][..], &args].concat()));
+}